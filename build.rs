@@ -0,0 +1,37 @@
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Generates the `UsageCode` catalog from `data/usages.tsv`.
+fn main() {
+    let source = "data/usages.tsv";
+    println!("cargo:rerun-if-changed={}", source);
+    let tsv = fs::read_to_string(source).expect("failed to read the usage catalog");
+
+    let mut codes = HashSet::new();
+    let mut variants = HashSet::new();
+    let mut out = String::from("usage_codes! {\n");
+    for (i, line) in tsv.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [code, variant, full] = fields[..] else {
+            panic!("{}:{}: expected code, variant and full name", source, i + 1);
+        };
+        assert!(
+            codes.insert(code) && variants.insert(variant) && variant != "Other",
+            "{}:{}: duplicate or reserved entry {:?}",
+            source,
+            i + 1,
+            line
+        );
+        writeln!(out, "    {} => ({:?}, {:?}),", variant, code, full).unwrap();
+    }
+    out.push_str("}\n");
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("usage_codes.rs");
+    fs::write(dest, out).expect("failed to write the usage catalog");
+}
@@ -0,0 +1,148 @@
+use crate::error::Error;
+use crate::types::{RateLimited, RemoteErrorKind};
+use std::sync::Mutex;
+
+/// How a `Session` adapts its limits to throttling by the server.
+///
+/// Each "service not available" error multiplies the rate the limiters apply by
+/// `backoff`, down to `min_rate`; every `recover_after` successful calls in a
+/// row multiply it by `recovery`, up to the configured limits again. A rate of
+/// 0.5 halves every limit, e.g. 200 instead of 400 calls per hour.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptivePolicy {
+    pub backoff: f64,
+    pub min_rate: f64,
+    pub recover_after: u32,
+    pub recovery: f64,
+}
+
+impl Default for AdaptivePolicy {
+    fn default() -> Self {
+        AdaptivePolicy {
+            backoff: 0.5,
+            min_rate: 0.1,
+            recover_after: 20,
+            recovery: 1.25,
+        }
+    }
+}
+
+impl AdaptivePolicy {
+    pub fn backoff(mut self, backoff: f64) -> Self {
+        self.backoff = backoff.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn min_rate(mut self, min_rate: f64) -> Self {
+        self.min_rate = min_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn recover_after(mut self, recover_after: u32) -> Self {
+        self.recover_after = recover_after.max(1);
+        self
+    }
+
+    pub fn recovery(mut self, recovery: f64) -> Self {
+        self.recovery = recovery.max(1.0);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct AdaptiveState {
+    rate: f64,
+    successes: u32,
+}
+
+/// The current rate under an `AdaptivePolicy`, shared by a session's clones.
+#[derive(Debug)]
+pub(crate) struct Adaptive {
+    policy: AdaptivePolicy,
+    state: Mutex<AdaptiveState>,
+}
+
+impl Adaptive {
+    pub(crate) fn new(policy: AdaptivePolicy) -> Adaptive {
+        Adaptive {
+            policy,
+            state: Mutex::new(AdaptiveState {
+                rate: 1.0,
+                successes: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn rate(&self) -> f64 {
+        self.state.lock().unwrap().rate
+    }
+
+    /// Adjusts the rate for the outcome of one call. Outcomes that say nothing
+    /// about the server's load, like local refusals, leave it alone.
+    pub(crate) fn observe<S>(&self, result: &RateLimited<S, Error>) {
+        let mut state = self.state.lock().unwrap();
+        match result {
+            RateLimited::Failed(Error::Remote(e))
+                if e.kind() == RemoteErrorKind::ServiceUnavailable =>
+            {
+                state.rate = (state.rate * self.policy.backoff).max(self.policy.min_rate);
+                state.successes = 0;
+            }
+            RateLimited::Allowed(_) => {
+                state.successes += 1;
+                if state.successes >= self.policy.recover_after {
+                    state.rate = (state.rate * self.policy.recovery).min(1.0);
+                    state.successes = 0;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RemoteError;
+
+    #[test]
+    fn test_adaptive_rate() {
+        let adaptive = Adaptive::new(
+            AdaptivePolicy::default()
+                .backoff(0.5)
+                .min_rate(0.2)
+                .recover_after(2)
+                .recovery(2.0),
+        );
+        let unavailable: RateLimited<(), Error> = RateLimited::Failed(Error::Remote(RemoteError {
+            error_code: 2,
+            error: "service not available".to_string(),
+        }));
+        let not_found: RateLimited<(), Error> = RateLimited::Failed(Error::Remote(RemoteError {
+            error_code: 50,
+            error: "name could not be found".to_string(),
+        }));
+        let allowed: RateLimited<(), Error> = RateLimited::Allowed(());
+
+        assert_eq!(adaptive.rate(), 1.0);
+        adaptive.observe(&unavailable);
+        assert_eq!(adaptive.rate(), 0.5);
+        adaptive.observe(&unavailable);
+        adaptive.observe(&unavailable);
+        assert_eq!(adaptive.rate(), 0.2);
+
+        adaptive.observe(&allowed);
+        adaptive.observe(&not_found);
+        assert_eq!(adaptive.rate(), 0.2);
+        adaptive.observe(&allowed);
+        assert_eq!(adaptive.rate(), 0.4);
+        adaptive.observe(&allowed);
+        adaptive.observe(&unavailable);
+        adaptive.observe(&allowed);
+        assert_eq!(adaptive.rate(), 0.2);
+        for _ in 0..6 {
+            adaptive.observe(&allowed);
+        }
+        assert_eq!(adaptive.rate(), 1.0);
+    }
+}
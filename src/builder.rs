@@ -0,0 +1,273 @@
+use crate::adaptive::AdaptivePolicy;
+use crate::error::Error;
+use crate::retry::RetryPolicy;
+use crate::session::{AsyncSession, Session, UsageLimit};
+use crate::transport::ReqwestTransport;
+use reqwest::{Certificate, Proxy};
+use std::path::PathBuf;
+use std::time::Duration;
+
+static DEFAULT_USER_AGENT: &str = concat!("behindthename.rs/", env!("CARGO_PKG_VERSION"));
+static DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Configures a `Session` or `AsyncSession` beyond a key and a `UsageLimit`.
+///
+/// The HTTP options (`timeout`, `connect_timeout`, `user_agent`, `proxy`,
+/// `add_root_certificate`, `danger_accept_invalid_certs`) only apply to the
+/// client the builder creates itself; they are ignored for an injected client.
+#[derive(Debug)]
+pub struct SessionBuilder {
+    key: String,
+    usage_limit: UsageLimit,
+    base_url: Option<String>,
+    state_file: Option<PathBuf>,
+    share_state_file: bool,
+    retry_policy: RetryPolicy,
+    adaptive: Option<AdaptivePolicy>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: String,
+    proxies: Vec<Proxy>,
+    root_certificates: Vec<Certificate>,
+    accept_invalid_certs: bool,
+    client: Option<reqwest::blocking::Client>,
+    async_client: Option<reqwest::Client>,
+}
+
+impl SessionBuilder {
+    pub fn new(key: impl Into<String>) -> SessionBuilder {
+        SessionBuilder {
+            key: key.into(),
+            usage_limit: UsageLimit::default(),
+            base_url: None,
+            state_file: None,
+            share_state_file: false,
+            retry_policy: RetryPolicy::none(),
+            adaptive: None,
+            timeout: Some(DEFAULT_TIMEOUT),
+            connect_timeout: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxies: vec![],
+            root_certificates: vec![],
+            accept_invalid_certs: false,
+            client: None,
+            async_client: None,
+        }
+    }
+
+    pub fn usage_limit(mut self, usage_limit: UsageLimit) -> Self {
+        self.usage_limit = usage_limit;
+        self
+    }
+
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Persists the limiters' state in `path`; see `Session::with_state_file`.
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self.share_state_file = false;
+        self
+    }
+
+    /// Shares the limiters' state with other processes through `path`; see
+    /// `Session::with_shared_state_file`.
+    pub fn shared_state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self.share_state_file = true;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Adapts the limits to server-side throttling; see `Session::with_adaptive`.
+    pub fn adaptive(mut self, policy: AdaptivePolicy) -> Self {
+        self.adaptive = Some(policy);
+        self
+    }
+
+    /// Total time allowed for each request; `None` disables the timeout.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Uses a pre-built blocking client for `build`.
+    pub fn client(mut self, client: reqwest::blocking::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Uses a pre-built async client for `build_async`.
+    pub fn async_client(mut self, client: reqwest::Client) -> Self {
+        self.async_client = Some(client);
+        self
+    }
+
+    pub fn build(self) -> Result<Session, Error> {
+        let client = match self.client {
+            Some(ref client) => client.clone(),
+            None => self.blocking_client()?,
+        };
+        let mut session =
+            Session::with_transport(self.key, &self.usage_limit, ReqwestTransport::new(client))
+                .with_retry_policy(self.retry_policy);
+        session = match self.state_file {
+            Some(path) if self.share_state_file => session.with_shared_state_file(path),
+            Some(path) => session.with_state_file(path)?,
+            None => session,
+        };
+        if let Some(policy) = self.adaptive {
+            session = session.with_adaptive(policy);
+        }
+        match self.base_url {
+            Some(base_url) => session.with_base_url(&base_url),
+            None => Ok(session),
+        }
+    }
+
+    pub fn build_async(self) -> Result<AsyncSession, Error> {
+        let client = match self.async_client {
+            Some(ref client) => client.clone(),
+            None => self.nonblocking_client()?,
+        };
+        let mut session = AsyncSession::with_client(self.key, &self.usage_limit, client)
+            .with_retry_policy(self.retry_policy);
+        session = match self.state_file {
+            Some(path) if self.share_state_file => session.with_shared_state_file(path),
+            Some(path) => session.with_state_file(path)?,
+            None => session,
+        };
+        if let Some(policy) = self.adaptive {
+            session = session.with_adaptive(policy);
+        }
+        match self.base_url {
+            Some(base_url) => session.with_base_url(&base_url),
+            None => Ok(session),
+        }
+    }
+
+    fn blocking_client(&self) -> Result<reqwest::blocking::Client, reqwest::Error> {
+        let mut builder = reqwest::blocking::Client::builder()
+            .timeout(self.timeout)
+            .user_agent(self.user_agent.as_str())
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        for proxy in &self.proxies {
+            builder = builder.proxy(proxy.clone());
+        }
+        for certificate in &self.root_certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        builder.build()
+    }
+
+    fn nonblocking_client(&self) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(self.user_agent.as_str())
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        for proxy in &self.proxies {
+            builder = builder.proxy(proxy.clone());
+        }
+        for certificate in &self.root_certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_default_session() {
+        let sesh = Session::builder("some_key").build().unwrap();
+        assert_eq!(sesh.key, "some_key");
+        assert_eq!(sesh.base_url(), "https://www.behindthename.com/");
+        assert_eq!(sesh.retry_policy(), &RetryPolicy::none());
+    }
+
+    #[test]
+    fn test_build_configured_session() {
+        let sesh = Session::builder("some_key")
+            .base_url("http://localhost:8080")
+            .retry_policy(RetryPolicy::default())
+            .adaptive(AdaptivePolicy::default())
+            .timeout(Some(Duration::from_secs(5)))
+            .connect_timeout(Duration::from_secs(1))
+            .user_agent("my-app/1.0")
+            .proxy(Proxy::all("http://proxy.local:3128").unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(sesh.base_url(), "http://localhost:8080/");
+        assert_eq!(sesh.retry_policy(), &RetryPolicy::default());
+        assert_eq!(sesh.effective_rate(), 1.0);
+    }
+
+    #[test]
+    fn test_build_with_client() {
+        let client = reqwest::blocking::Client::new();
+        let _ = Session::builder("some_key").client(client).build().unwrap();
+        let async_client = reqwest::Client::new();
+        let _ = Session::builder("some_key")
+            .async_client(async_client)
+            .build_async()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_build_async_keeps_retry_policy() {
+        let sesh = Session::builder("some_key")
+            .retry_policy(RetryPolicy::default())
+            .build_async()
+            .unwrap();
+        assert_eq!(sesh.retry_policy(), &RetryPolicy::default());
+    }
+
+    #[test]
+    fn test_build_invalid_base_url() {
+        match Session::builder("some_key").base_url("not a url").build() {
+            Err(Error::Url(_)) => (),
+            _ => panic!("expected a base URL error"),
+        }
+    }
+}
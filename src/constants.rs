@@ -1,3 +1,4 @@
-pub static LOOKUP_JSON_URL: &str = "https://www.behindthename.com/api/lookup.json";
-pub static RANDOM_JSON_URL: &str = "https://www.behindthename.com/api/random.json";
-pub static RELATED_JSON_URL: &str = "https://www.behindthename.com/api/related.json";
+pub static DEFAULT_BASE_URL: &str = "https://www.behindthename.com/";
+pub static LOOKUP_JSON_PATH: &str = "api/lookup.json";
+pub static RANDOM_JSON_PATH: &str = "api/random.json";
+pub static RELATED_JSON_PATH: &str = "api/related.json";
@@ -0,0 +1,108 @@
+use crate::types::RemoteError;
+use http::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request never produced a response, e.g. a connection failure.
+    Transport(BoxError),
+    /// The server answered with a non-success status and no API error in the body.
+    Status { status: StatusCode, body: String },
+    /// The body matched none of the response types; the raw text is attached.
+    Decode {
+        body: String,
+        source: serde_json::Error,
+    },
+    /// The API answered with an error object.
+    Remote(RemoteError),
+    /// Reading or writing a limiter state file failed.
+    Io(std::io::Error),
+    /// A base URL could not be parsed.
+    Url(url::ParseError),
+    /// A stored `Record` uses a format version this crate does not know.
+    UnsupportedRecordVersion(u32),
+    /// A request parameter was rejected locally, before any quota was spent.
+    InvalidParameter {
+        parameter: &'static str,
+        reason: String,
+    },
+    /// Every key in a `KeyPool` has been taken out of rotation.
+    NoKeys,
+    /// A local limiter refused the call; `wait` is how long until it would allow it.
+    Governed { interval: String, wait: Duration },
+    /// Waiting for a local limiter would have taken longer than allowed.
+    TimedOut { interval: String, wait: Duration },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::Status { status, .. } => write!(f, "unexpected HTTP status {}", status),
+            Error::Decode { body, source } => {
+                write!(f, "failed to decode response {:?}: {}", body, source)
+            }
+            Error::Remote(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Url(e) => write!(f, "invalid URL: {}", e),
+            Error::UnsupportedRecordVersion(v) => write!(f, "unsupported record version {}", v),
+            Error::InvalidParameter { parameter, reason } => {
+                write!(f, "invalid {}: {}", parameter, reason)
+            }
+            Error::NoKeys => write!(f, "no API keys left in rotation"),
+            Error::Governed { interval, wait } => {
+                write!(f, "governed by the {} limiter for {:?}", interval, wait)
+            }
+            Error::TimedOut { interval, wait } => write!(
+                f,
+                "timed out: the {} limiter would need {:?} more",
+                interval, wait
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e.as_ref()),
+            Error::Decode { source, .. } => Some(source),
+            Error::Io(e) => Some(e),
+            Error::Url(e) => Some(e),
+            Error::Status { .. }
+            | Error::Remote(_)
+            | Error::InvalidParameter { .. }
+            | Error::UnsupportedRecordVersion(_)
+            | Error::NoKeys
+            | Error::Governed { .. }
+            | Error::TimedOut { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Transport(Box::new(e))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::Url(e)
+    }
+}
+
+impl From<RemoteError> for Error {
+    fn from(e: RemoteError) -> Self {
+        Error::Remote(e)
+    }
+}
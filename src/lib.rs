@@ -1,11 +1,23 @@
+pub mod adaptive;
+pub mod builder;
+pub mod error;
+pub mod limiter;
 pub mod lookup;
+pub mod pool;
 pub mod random;
+pub mod record;
 pub mod related;
-
+pub mod request;
+pub mod retry;
+pub mod sample;
 pub mod session;
+pub mod transport;
 pub mod types;
+pub mod usage;
 
 mod constants;
 
+pub use error::Error;
+
 #[cfg(test)]
 mod tests {}
@@ -0,0 +1,972 @@
+use crate::error::Error;
+use crate::session::UsageLimit;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// When a governed call could next be allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotUntil {
+    earliest: SystemTime,
+}
+
+impl NotUntil {
+    fn from_nanos(nanos: u64) -> NotUntil {
+        NotUntil {
+            earliest: UNIX_EPOCH + Duration::from_nanos(nanos),
+        }
+    }
+
+    pub fn earliest_possible(&self) -> SystemTime {
+        self.earliest
+    }
+
+    pub fn wait_time_from(&self, from: SystemTime) -> Duration {
+        self.earliest.duration_since(from).unwrap_or(Duration::ZERO)
+    }
+
+    pub fn wait_time(&self) -> Duration {
+        self.wait_time_from(SystemTime::now())
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_DAY: i64 = 86_400 * NANOS_PER_SEC as i64;
+
+/// How often `until_ready` polls a shared state file another process has locked.
+const LOCK_RETRY: Duration = Duration::from_millis(10);
+
+/// The span of time a `Limit` counts calls over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Window {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    /// 365 days when smoothed; the calendar year in `LimiterMode::FixedWindow`.
+    Year,
+    Seconds(NonZeroU64),
+}
+
+impl Window {
+    pub fn period(&self) -> Duration {
+        match self {
+            Window::Second => Duration::from_secs(1),
+            Window::Minute => Duration::from_secs(60),
+            Window::Hour => Duration::from_secs(60 * 60),
+            Window::Day => Duration::from_secs(60 * 60 * 24),
+            Window::Year => Duration::from_secs(60 * 60 * 24 * 365),
+            Window::Seconds(n) => Duration::from_secs(n.get()),
+        }
+    }
+}
+
+/// Names the window the way `Governed` and `TimedOut` report it, e.g. `"Hour"`
+/// or `"10 seconds"`.
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Window::Second => write!(f, "Second"),
+            Window::Minute => write!(f, "Minute"),
+            Window::Hour => write!(f, "Hour"),
+            Window::Day => write!(f, "Day"),
+            Window::Year => write!(f, "Year"),
+            Window::Seconds(n) => write!(f, "{} seconds", n),
+        }
+    }
+}
+
+/// At most `count` calls per `window`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Limit {
+    pub count: NonZeroU64,
+    pub window: Window,
+}
+
+impl Limit {
+    pub fn new(count: NonZeroU64, window: Window) -> Limit {
+        Limit { count, window }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} per {}",
+            self.count,
+            self.window.to_string().to_lowercase()
+        )
+    }
+}
+
+/// How the limiters count calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimiterMode {
+    /// Spreads each limit evenly over its period (GCRA): after a burst, calls
+    /// free up one at a time, e.g. one every 21.6 seconds for 4,000 a day.
+    #[default]
+    Smoothed,
+    /// Counts calls in fixed windows that reset all at once on calendar
+    /// boundaries: every second, hour and midnight, and on January 1 for the
+    /// yearly limit, taken in the time zone `utc_offset_secs` east of UTC.
+    FixedWindow { utc_offset_secs: i32 },
+}
+
+/// One limiter's state. A smoothed limiter only uses `tat`, its theoretical
+/// arrival time; a fixed-window one counts calls since `window_start`. Times
+/// are Unix nanoseconds, which is what makes the state cheap to save and restore.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Slot {
+    #[serde(default)]
+    tat: u64,
+    #[serde(default)]
+    window_start: u64,
+    #[serde(default)]
+    count: u64,
+}
+
+/// Allows `limit` calls per `window`, all of which may be spent in a burst.
+#[derive(Debug, Clone)]
+struct Limiter {
+    interval: String,
+    limit: u64,
+    window: Window,
+    period: Duration,
+    mode: LimiterMode,
+    emission: u64,
+    tolerance: u64,
+}
+
+impl Limiter {
+    fn new(limit: u64, window: Window, mode: LimiterMode) -> Limiter {
+        let period = window.period();
+        let emission = (period.as_nanos() / limit as u128).max(1) as u64;
+        Limiter {
+            interval: window.to_string(),
+            limit,
+            window,
+            period,
+            mode,
+            emission,
+            tolerance: emission.saturating_mul(limit - 1),
+        }
+    }
+
+    /// When the next call is allowed; at or before `now` if it is allowed now.
+    fn earliest(&self, slot: &Slot, now: u64) -> u64 {
+        match self.mode {
+            LimiterMode::Smoothed => slot.tat.saturating_sub(self.tolerance),
+            LimiterMode::FixedWindow { .. } => {
+                let (start, end) = self.window(now);
+                if slot.window_start == start && slot.count >= self.limit {
+                    end
+                } else {
+                    now
+                }
+            }
+        }
+    }
+
+    fn take(&self, slot: &mut Slot, now: u64) {
+        match self.mode {
+            LimiterMode::Smoothed => slot.tat = slot.tat.max(now) + self.emission,
+            LimiterMode::FixedWindow { .. } => {
+                let (start, _) = self.window(now);
+                if slot.window_start != start {
+                    *slot = Slot {
+                        window_start: start,
+                        ..Slot::default()
+                    };
+                }
+                slot.count += 1;
+            }
+        }
+    }
+
+    /// Pulls back state left ahead of `now` by a wall clock that has since
+    /// stepped backward, e.g. by an NTP correction, to where a full burst at
+    /// `now` would put it. The step then costs at most one window's wait.
+    fn clamp(&self, slot: &mut Slot, now: u64) {
+        match self.mode {
+            LimiterMode::Smoothed => {
+                slot.tat = slot
+                    .tat
+                    .min(now.saturating_add(self.limit.saturating_mul(self.emission)))
+            }
+            LimiterMode::FixedWindow { .. } => {
+                if slot.window_start > now {
+                    slot.window_start = self.window(now).0;
+                }
+            }
+        }
+    }
+
+    /// How many calls still count against the limit at `now`.
+    fn outstanding(&self, slot: &Slot, now: u64) -> u64 {
+        match self.mode {
+            LimiterMode::Smoothed => slot.tat.saturating_sub(now).div_ceil(self.emission),
+            LimiterMode::FixedWindow { .. } if slot.window_start == self.window(now).0 => {
+                slot.count
+            }
+            LimiterMode::FixedWindow { .. } => 0,
+        }
+    }
+
+    fn with_outstanding(&self, outstanding: u64, now: u64) -> Slot {
+        match self.mode {
+            LimiterMode::Smoothed => Slot {
+                tat: now + outstanding * self.emission,
+                ..Slot::default()
+            },
+            LimiterMode::FixedWindow { .. } => Slot {
+                window_start: self.window(now).0,
+                count: outstanding,
+                ..Slot::default()
+            },
+        }
+    }
+
+    /// The bounds of the fixed window containing `now`. A yearly limit follows
+    /// the calendar; shorter periods are counted from local midnight, January 1 1970.
+    fn window(&self, now: u64) -> (u64, u64) {
+        let offset = match self.mode {
+            LimiterMode::FixedWindow { utc_offset_secs } => {
+                utc_offset_secs as i64 * NANOS_PER_SEC as i64
+            }
+            LimiterMode::Smoothed => 0,
+        };
+        let local = now as i64 + offset;
+        let (start, end) = if self.window == Window::Year {
+            year_bounds(local)
+        } else {
+            let period = self.period.as_nanos() as i64;
+            let start = local.div_euclid(period) * period;
+            (start, start + period)
+        };
+        ((start - offset).max(0) as u64, (end - offset).max(0) as u64)
+    }
+}
+
+impl Limiter {
+    fn status(&self, slot: &Slot, now: u64) -> QuotaStatus {
+        let (remaining, full_at) = match self.mode {
+            LimiterMode::Smoothed => {
+                let tat = slot.tat.max(now);
+                let room = (now + self.tolerance).saturating_sub(tat - self.emission);
+                ((room / self.emission).min(self.limit), slot.tat)
+            }
+            LimiterMode::FixedWindow { .. } => {
+                let used = self.outstanding(slot, now);
+                let full_at = if used > 0 { self.window(now).1 } else { now };
+                (self.limit.saturating_sub(used), full_at)
+            }
+        };
+        QuotaStatus {
+            interval: self.interval.clone(),
+            limit: self.limit,
+            remaining,
+            replenished_in: Duration::from_nanos(full_at.saturating_sub(now)),
+        }
+    }
+}
+
+/// Where one limit stands, as reported by `Session::quota_status`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct QuotaStatus {
+    pub interval: String,
+    pub limit: u64,
+    /// How many calls this limit would allow right now.
+    pub remaining: u64,
+    /// How long until `remaining` is back up to `limit` if no calls are made.
+    pub replenished_in: Duration,
+}
+
+/// Days from January 1 1970 to January 1 of `year`.
+fn days_to_year(year: i64) -> i64 {
+    let y = year - 1;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    era * 146_097 + yoe * 365 + yoe / 4 - yoe / 100 + 306 - 719_468
+}
+
+/// The bounds of the calendar year containing `local`, all in nanoseconds.
+fn year_bounds(local: i64) -> (i64, i64) {
+    let days = local.div_euclid(NANOS_PER_DAY);
+    let mut year = 1970 + days.div_euclid(365);
+    while days_to_year(year) > days {
+        year -= 1;
+    }
+    while days_to_year(year + 1) <= days {
+        year += 1;
+    }
+    (
+        days_to_year(year) * NANOS_PER_DAY,
+        days_to_year(year + 1) * NANOS_PER_DAY,
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct IntervalState {
+    interval: String,
+    limit: u64,
+    period_secs: u64,
+    #[serde(default)]
+    mode: LimiterMode,
+    #[serde(flatten)]
+    slot: Slot,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct StateFile {
+    intervals: Vec<IntervalState>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Rejected {
+    Limited(String, NotUntil),
+    Store(Error),
+}
+
+/// Where a limiter set keeps its state besides memory.
+enum Store {
+    Memory,
+    /// Read once on construction and rewritten after every allowed call.
+    File(PathBuf),
+    /// Read, updated and rewritten under an exclusive lock on every check, so
+    /// every process using the file draws from the same quota.
+    Shared(PathBuf),
+}
+
+/// The limiters in force at `rate`, a fraction of the configured limits, and
+/// their state.
+struct Scaled {
+    rate: f64,
+    limiters: Vec<Limiter>,
+    slots: Vec<Slot>,
+}
+
+pub(crate) struct RateLimiters {
+    limits: UsageLimit,
+    limiters: Vec<Limiter>,
+    state: Mutex<Scaled>,
+    store: Store,
+}
+
+impl fmt::Display for RateLimiters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limits: Vec<String> = self.limits.limits.iter().map(Limit::to_string).collect();
+        write!(f, "RateLimiters({})", limits.join(", "))
+    }
+}
+
+impl RateLimiters {
+    pub(crate) fn new(limits: UsageLimit) -> RateLimiters {
+        let limiters: Vec<Limiter> = limits
+            .limits
+            .iter()
+            .map(|limit| Limiter::new(limit.count.get(), limit.window, limits.mode))
+            .collect();
+        RateLimiters {
+            limits,
+            state: Mutex::new(Scaled {
+                rate: 1.0,
+                limiters: limiters.clone(),
+                slots: vec![Slot::default(); limiters.len()],
+            }),
+            limiters,
+            store: Store::Memory,
+        }
+    }
+
+    /// Restores the limiters from `path` if it exists, and saves them back to it
+    /// after every call they allow.
+    pub(crate) fn with_state_file(
+        limits: UsageLimit,
+        path: impl AsRef<Path>,
+    ) -> Result<RateLimiters, Error> {
+        let mut limiters = RateLimiters::new(limits);
+        let path = path.as_ref().to_path_buf();
+        if let Some(saved) = read_state(&path)? {
+            let state = limiters.state.get_mut().unwrap();
+            state.slots = restore(&state.limiters, &saved, unix_nanos(SystemTime::now()));
+        }
+        limiters.store = Store::File(path);
+        Ok(limiters)
+    }
+
+    /// Keeps the limiters' state only in `path`, which other processes may use
+    /// at the same time. The file is created if it does not exist.
+    pub(crate) fn with_shared_state_file(
+        limits: UsageLimit,
+        path: impl AsRef<Path>,
+    ) -> RateLimiters {
+        RateLimiters {
+            store: Store::Shared(path.as_ref().to_path_buf()),
+            ..RateLimiters::new(limits)
+        }
+    }
+
+    pub(crate) fn limits(&self) -> &UsageLimit {
+        &self.limits
+    }
+
+    /// Takes one call from every limiter, each scaled down to `rate` of its
+    /// limit, or none if any of them would refuse it. The refusal names the
+    /// limiter that frees up last.
+    pub(crate) fn check(&self, rate: f64) -> Result<(), Rejected> {
+        self.check_with(rate, |file| file.lock_exclusive())
+    }
+
+    /// Like `check`, but fails with a lock-contended I/O error instead of
+    /// blocking while another process holds the shared state file.
+    pub(crate) fn try_check(&self, rate: f64) -> Result<(), Rejected> {
+        self.check_with(rate, |file| file.try_lock_exclusive())
+    }
+
+    fn check_with(
+        &self,
+        rate: f64,
+        lock: impl FnOnce(&File) -> io::Result<()>,
+    ) -> Result<(), Rejected> {
+        let mut state = self.state.lock().unwrap();
+        let now = unix_nanos(SystemTime::now());
+        self.rescale(&mut state, rate, now);
+        let Scaled {
+            limiters, slots, ..
+        } = &mut *state;
+        match &self.store {
+            Store::Memory => take(limiters, slots, now),
+            Store::File(path) => {
+                take(limiters, slots, now)?;
+                write_state(path, &snapshot(limiters, slots)).map_err(Rejected::Store)
+            }
+            Store::Shared(path) => take_shared(path, limiters, slots, now, lock),
+        }
+    }
+
+    /// Reports every limit, scaled down to `rate`, without taking a call. With
+    /// a shared state file this reflects the other processes' calls too.
+    pub(crate) fn status(&self, rate: f64) -> Result<Vec<QuotaStatus>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = unix_nanos(SystemTime::now());
+        self.rescale(&mut state, rate, now);
+        let slots = match &self.store {
+            Store::Shared(path) => {
+                read_shared(path, &state.limiters, now)?.unwrap_or(state.slots.clone())
+            }
+            Store::Memory | Store::File(_) => state.slots.clone(),
+        };
+        Ok(state
+            .limiters
+            .iter()
+            .zip(&slots)
+            .map(|(limiter, &slot)| {
+                let mut slot = slot;
+                limiter.clamp(&mut slot, now);
+                limiter.status(&slot, now)
+            })
+            .collect())
+    }
+
+    /// Swaps in limiters scaled to `rate` if it changed, carrying over the
+    /// calls still counted against each.
+    fn rescale(&self, state: &mut Scaled, rate: f64, now: u64) {
+        if state.rate == rate {
+            return;
+        }
+        let limiters: Vec<Limiter> = self
+            .limiters
+            .iter()
+            .map(|limiter| {
+                let limit = ((limiter.limit as f64 * rate).floor() as u64).clamp(1, limiter.limit);
+                Limiter::new(limit, limiter.window, limiter.mode)
+            })
+            .collect();
+        state.slots = limiters
+            .iter()
+            .zip(state.limiters.iter().zip(&state.slots))
+            .map(|(new, (old, slot))| new.with_outstanding(old.outstanding(slot, now), now))
+            .collect();
+        state.limiters = limiters;
+        state.rate = rate;
+    }
+
+    /// Waits without blocking the executor, polling a locked shared state file
+    /// rather than waiting on the lock.
+    pub(crate) async fn until_ready(&self, rate: f64) -> Result<(), Error> {
+        loop {
+            match self.try_check(rate) {
+                Ok(()) => return Ok(()),
+                Err(Rejected::Limited(_, earliest)) => {
+                    futures_timer::Delay::new(earliest.wait_time()).await
+                }
+                Err(Rejected::Store(Error::Io(e))) if is_contended(&e) => {
+                    futures_timer::Delay::new(LOCK_RETRY).await
+                }
+                Err(Rejected::Store(e)) => return Err(e),
+            }
+        }
+    }
+}
+
+fn is_contended(e: &io::Error) -> bool {
+    e.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+}
+
+fn read_shared(path: &Path, limiters: &[Limiter], now: u64) -> Result<Option<Vec<Slot>>, Error> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    file.lock_shared()?;
+    let mut body = String::new();
+    let read = file.read_to_string(&mut body);
+    let _ = file.unlock();
+    read?;
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
+    match serde_json::from_str(&body) {
+        Ok(saved) => Ok(Some(restore(limiters, &saved, now))),
+        Err(source) => Err(Error::Decode { body, source }),
+    }
+}
+
+fn take(limiters: &[Limiter], slots: &mut [Slot], now: u64) -> Result<(), Rejected> {
+    for (limiter, slot) in limiters.iter().zip(slots.iter_mut()) {
+        limiter.clamp(slot, now);
+    }
+    let refusal = limiters
+        .iter()
+        .zip(slots.iter())
+        .map(|(limiter, slot)| (&limiter.interval, limiter.earliest(slot, now)))
+        .filter(|&(_, earliest)| earliest > now)
+        .max_by_key(|&(_, earliest)| earliest);
+    if let Some((interval, earliest)) = refusal {
+        return Err(Rejected::Limited(
+            interval.clone(),
+            NotUntil::from_nanos(earliest),
+        ));
+    }
+    for (limiter, slot) in limiters.iter().zip(slots.iter_mut()) {
+        limiter.take(slot, now);
+    }
+    Ok(())
+}
+
+fn take_shared(
+    path: &Path,
+    limiters: &[Limiter],
+    slots: &mut [Slot],
+    now: u64,
+    lock: impl FnOnce(&File) -> io::Result<()>,
+) -> Result<(), Rejected> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| Rejected::Store(e.into()))?;
+    lock(&file).map_err(|e| Rejected::Store(e.into()))?;
+    let result = take_locked(&mut file, limiters, slots, now);
+    let _ = file.unlock();
+    result
+}
+
+fn take_locked(
+    file: &mut File,
+    limiters: &[Limiter],
+    slots: &mut [Slot],
+    now: u64,
+) -> Result<(), Rejected> {
+    let mut body = String::new();
+    file.read_to_string(&mut body)
+        .map_err(|e| Rejected::Store(e.into()))?;
+    if !body.trim().is_empty() {
+        let saved: StateFile = serde_json::from_str(&body)
+            .map_err(|source| Rejected::Store(Error::Decode { body, source }))?;
+        slots.copy_from_slice(&restore(limiters, &saved, now));
+    }
+    take(limiters, slots, now)?;
+    let body = serde_json::to_string(&snapshot(limiters, slots)).unwrap();
+    file.set_len(0)
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .and_then(|_| file.write_all(body.as_bytes()))
+        .map_err(|e| Rejected::Store(e.into()))
+}
+
+fn snapshot(limiters: &[Limiter], slots: &[Slot]) -> StateFile {
+    StateFile {
+        intervals: limiters
+            .iter()
+            .zip(slots)
+            .map(|(limiter, &slot)| IntervalState {
+                interval: limiter.interval.clone(),
+                limit: limiter.limit,
+                period_secs: limiter.period.as_secs(),
+                mode: limiter.mode,
+                slot,
+            })
+            .collect(),
+    }
+}
+
+/// Maps saved state onto `limiters` by interval name. If an interval's limit,
+/// period or mode changed, the calls still counted against it carry over.
+fn restore(limiters: &[Limiter], saved: &StateFile, now: u64) -> Vec<Slot> {
+    limiters
+        .iter()
+        .map(|limiter| {
+            let mut slot = match saved
+                .intervals
+                .iter()
+                .find(|s| s.interval == limiter.interval && s.limit > 0 && s.period_secs > 0)
+            {
+                None => Slot::default(),
+                Some(s)
+                    if s.limit == limiter.limit
+                        && s.period_secs == limiter.period.as_secs()
+                        && s.mode == limiter.mode =>
+                {
+                    s.slot
+                }
+                Some(s) => {
+                    let window = match NonZeroU64::new(s.period_secs) {
+                        Some(secs) if s.period_secs != limiter.period.as_secs() => {
+                            Window::Seconds(secs)
+                        }
+                        _ => limiter.window,
+                    };
+                    let old = Limiter::new(s.limit, window, s.mode);
+                    limiter.with_outstanding(old.outstanding(&s.slot, now), now)
+                }
+            };
+            limiter.clamp(&mut slot, now);
+            slot
+        })
+        .collect()
+}
+
+fn read_state(path: &Path) -> Result<Option<StateFile>, Error> {
+    let body = match fs::read_to_string(path) {
+        Ok(body) => body,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match serde_json::from_str(&body) {
+        Ok(state) => Ok(Some(state)),
+        Err(source) => Err(Error::Decode { body, source }),
+    }
+}
+
+fn write_state(path: &Path, state: &StateFile) -> Result<(), Error> {
+    let body = serde_json::to_string(state).unwrap();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, body)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonzero_ext::nonzero;
+
+    fn limits() -> UsageLimit {
+        UsageLimit::test_limits(10, 10, 3, 10)
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "behindthename-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_check_is_all_or_nothing() {
+        let limiters = RateLimiters::new(UsageLimit::test_limits(2, 3, 10, 10));
+        assert!(limiters.check(1.0).is_ok());
+        assert!(limiters.check(1.0).is_ok());
+        match limiters.check(1.0) {
+            Err(Rejected::Limited(interval, earliest)) => {
+                assert_eq!(interval, "Second");
+                assert!(earliest.wait_time() <= Duration::from_secs(1));
+            }
+            _ => panic!("the per-second limiter should refuse a third call"),
+        }
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(limiters.check(1.0).is_ok());
+        match limiters.check(1.0) {
+            Err(Rejected::Limited(interval, _)) => assert_eq!(interval, "Hour"),
+            _ => panic!("the hourly limiter should refuse a fourth call"),
+        }
+    }
+
+    #[test]
+    fn test_state_file_survives_restart() {
+        let path = state_path("restart");
+        let limiters = RateLimiters::with_state_file(limits(), &path).unwrap();
+        assert!(limiters.check(1.0).is_ok());
+        assert!(limiters.check(1.0).is_ok());
+        drop(limiters);
+
+        let limiters = RateLimiters::with_state_file(limits(), &path).unwrap();
+        assert!(limiters.check(1.0).is_ok());
+        match limiters.check(1.0) {
+            Err(Rejected::Limited(interval, _)) => assert_eq!(interval, "Day"),
+            _ => panic!("the daily budget should carry over from the first run"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_state_file_carries_over_changed_limits() {
+        let path = state_path("changed");
+        let limiters = RateLimiters::with_state_file(limits(), &path).unwrap();
+        assert!(limiters.check(1.0).is_ok());
+        assert!(limiters.check(1.0).is_ok());
+        drop(limiters);
+
+        let limiters =
+            RateLimiters::with_state_file(UsageLimit::test_limits(10, 10, 4, 10), &path).unwrap();
+        assert!(limiters.check(1.0).is_ok());
+        assert!(limiters.check(1.0).is_ok());
+        match limiters.check(1.0) {
+            Err(Rejected::Limited(interval, _)) => assert_eq!(interval, "Day"),
+            _ => panic!("two of the four daily calls were already spent"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_state_file() {
+        let path = state_path("corrupt");
+        fs::write(&path, "not json").unwrap();
+        match RateLimiters::with_state_file(limits(), &path) {
+            Err(Error::Decode { body, .. }) => assert_eq!(body, "not json"),
+            _ => panic!("expected a decode error"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shared_state_file() {
+        let path = state_path("shared");
+        let first = RateLimiters::with_shared_state_file(limits(), &path);
+        let second = RateLimiters::with_shared_state_file(limits(), &path);
+        assert!(first.check(1.0).is_ok());
+        assert!(second.check(1.0).is_ok());
+        assert!(first.check(1.0).is_ok());
+        match second.check(1.0) {
+            Err(Rejected::Limited(interval, _)) => assert_eq!(interval, "Day"),
+            _ => panic!("the daily budget should be shared"),
+        }
+
+        fs::remove_file(&path).unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    RateLimiters::with_shared_state_file(limits(), path)
+                        .check(1.0)
+                        .is_ok()
+                })
+            })
+            .collect();
+        let allowed = threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .filter(|&allowed| allowed)
+            .count();
+        assert_eq!(allowed, 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_try_check_does_not_wait_for_the_lock() {
+        let path = state_path("locked");
+        let limiters = RateLimiters::with_shared_state_file(limits(), &path);
+        assert!(limiters.try_check(1.0).is_ok());
+
+        let holder = File::open(&path).unwrap();
+        holder.lock_exclusive().unwrap();
+        match limiters.try_check(1.0) {
+            Err(Rejected::Store(Error::Io(e))) => assert!(is_contended(&e)),
+            r => panic!("expected a contended lock: {:?}", r),
+        }
+        holder.unlock().unwrap();
+        assert!(limiters.try_check(1.0).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_year_bounds() {
+        let day = NANOS_PER_DAY;
+        assert_eq!(days_to_year(1970), 0);
+        assert_eq!(days_to_year(2024), 19_723);
+        assert_eq!(days_to_year(2025), 20_089);
+        assert_eq!(year_bounds(0), (0, 365 * day));
+        assert_eq!(
+            year_bounds(19_723 * day + 12_345),
+            (19_723 * day, 20_089 * day)
+        );
+        assert_eq!(year_bounds(20_089 * day - 1), (19_723 * day, 20_089 * day));
+        assert_eq!(year_bounds(-1), (-365 * day, 0));
+    }
+
+    #[test]
+    fn test_fixed_window_resets_at_midnight() {
+        let day = NANOS_PER_DAY as u64;
+        let limiters =
+            RateLimiters::new(limits().with_mode(LimiterMode::FixedWindow { utc_offset_secs: 0 }));
+        let mut slots = vec![Slot::default(); 4];
+        let evening = 20_000 * day + 23 * 3600 * NANOS_PER_SEC;
+        for i in 0..3 {
+            assert!(take(&limiters.limiters, &mut slots, evening + i).is_ok());
+        }
+        match take(&limiters.limiters, &mut slots, evening + 3) {
+            Err(Rejected::Limited(interval, earliest)) => {
+                assert_eq!(interval, "Day");
+                assert_eq!(
+                    earliest.earliest_possible(),
+                    UNIX_EPOCH + Duration::from_secs(20_001 * 86_400)
+                );
+            }
+            _ => panic!("the daily window should be full"),
+        }
+        for i in 0..3 {
+            assert!(take(&limiters.limiters, &mut slots, 20_001 * day + i).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_clock_stepping_back_costs_at_most_one_window() {
+        let limiters = RateLimiters::new(UsageLimit::test_limits(2, 10, 100, 1000));
+        let mut slots = vec![Slot::default(); 4];
+        let hour = 3600 * NANOS_PER_SEC;
+        let now = 20_000 * NANOS_PER_DAY as u64;
+        assert!(take(&limiters.limiters, &mut slots, now + hour).is_ok());
+        assert!(take(&limiters.limiters, &mut slots, now + hour).is_ok());
+        match take(&limiters.limiters, &mut slots, now) {
+            Err(Rejected::Limited(interval, earliest)) => {
+                assert_eq!(interval, "Hour");
+                assert_eq!(
+                    earliest.wait_time_from(UNIX_EPOCH + Duration::from_nanos(now)),
+                    Duration::from_secs(360)
+                );
+            }
+            _ => panic!("the hourly limiter should still count the calls"),
+        }
+        assert!(take(&limiters.limiters, &mut slots, now + 360 * NANOS_PER_SEC).is_ok());
+
+        let day = NANOS_PER_DAY as u64;
+        let limiters =
+            RateLimiters::new(limits().with_mode(LimiterMode::FixedWindow { utc_offset_secs: 0 }));
+        let mut slots = vec![Slot::default(); 4];
+        for i in 0..3 {
+            assert!(take(&limiters.limiters, &mut slots, 20_001 * day + i).is_ok());
+        }
+        match take(&limiters.limiters, &mut slots, 20_000 * day + hour) {
+            Err(Rejected::Limited(interval, earliest)) => {
+                assert_eq!(interval, "Day");
+                assert_eq!(
+                    earliest.earliest_possible(),
+                    UNIX_EPOCH + Duration::from_secs(20_001 * 86_400)
+                );
+            }
+            _ => panic!("calls from the later window should count against the current one"),
+        }
+    }
+
+    #[test]
+    fn test_only_the_year_window_follows_the_calendar() {
+        let mode = LimiterMode::FixedWindow { utc_offset_secs: 0 };
+        let day = NANOS_PER_DAY as u64;
+        let now = 20_000 * day;
+        let year = Limiter::new(3, Window::Year, mode);
+        assert_eq!(year.window(now), (19_723 * day, 20_089 * day));
+        let seconds = Limiter::new(3, Window::Seconds(nonzero!(31_536_000u64)), mode);
+        assert_eq!(seconds.window(now), (19_710 * day, 20_075 * day));
+    }
+
+    #[test]
+    fn test_fixed_window_utc_offset() {
+        let limiter = Limiter::new(
+            3,
+            Window::Day,
+            LimiterMode::FixedWindow {
+                utc_offset_secs: -5 * 3600,
+            },
+        );
+        let day = NANOS_PER_DAY as u64;
+        let hour = 3600 * NANOS_PER_SEC;
+        assert_eq!(
+            limiter.window(20_000 * day + hour),
+            (19_999 * day + 5 * hour, 20_000 * day + 5 * hour)
+        );
+    }
+
+    #[test]
+    fn test_state_file_carries_over_changed_mode() {
+        let path = state_path("mode");
+        let limiters = RateLimiters::with_state_file(limits(), &path).unwrap();
+        assert!(limiters.check(1.0).is_ok());
+        assert!(limiters.check(1.0).is_ok());
+        drop(limiters);
+
+        let fixed = limits().with_mode(LimiterMode::FixedWindow { utc_offset_secs: 0 });
+        let limiters = RateLimiters::with_state_file(fixed, &path).unwrap();
+        assert!(limiters.check(1.0).is_ok());
+        match limiters.check(1.0) {
+            Err(Rejected::Limited(interval, _)) => assert_eq!(interval, "Day"),
+            _ => panic!("calls made in smoothed mode should still count"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fixed_window_status() {
+        let limiters =
+            RateLimiters::new(limits().with_mode(LimiterMode::FixedWindow { utc_offset_secs: 0 }));
+        let day = NANOS_PER_DAY as u64;
+        let now = 20_000 * day + 18 * 3600 * NANOS_PER_SEC;
+        let mut slots = vec![Slot::default(); 4];
+        take(&limiters.limiters, &mut slots, now).unwrap();
+        let status = limiters.limiters[2].status(&slots[2], now);
+        assert_eq!(status.interval, "Day");
+        assert_eq!(status.remaining, 2);
+        assert_eq!(status.replenished_in, Duration::from_secs(6 * 3600));
+    }
+
+    #[test]
+    fn test_shared_state_file_status() {
+        let path = state_path("shared-status");
+        let first = RateLimiters::with_shared_state_file(limits(), &path);
+        let second = RateLimiters::with_shared_state_file(limits(), &path);
+        assert_eq!(second.status(1.0).unwrap()[2].remaining, 3);
+        assert!(first.check(1.0).is_ok());
+        assert_eq!(second.status(1.0).unwrap()[2].remaining, 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,26 +1,53 @@
-use crate::constants::LOOKUP_JSON_URL;
-use url::Url;
+use crate::constants::LOOKUP_JSON_PATH;
+use crate::error::Error;
+use crate::request::{validate_name, Request, RequestContext};
+use crate::types::JsonNameDetails;
+use serde::{Deserialize, Serialize};
 
-fn _lookup(name: &str, exact: bool) -> impl FnOnce(&str) -> String + '_ {
-    move |key| {
-        let mut params: Vec<(&str, &str)> = vec![("key", key), ("name", name)];
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LookupRequest {
+    pub name: String,
+    pub exact: bool,
+}
+
+impl LookupRequest {
+    pub fn new(name: impl Into<String>) -> LookupRequest {
+        LookupRequest {
+            name: name.into(),
+            exact: false,
+        }
+    }
+
+    pub fn exact(mut self) -> Self {
+        self.exact = true;
+        self
+    }
+}
 
-        if exact {
+impl Request for LookupRequest {
+    type Response = JsonNameDetails;
+
+    fn url(&self, ctx: &RequestContext) -> Result<String, Error> {
+        let mut params: Vec<(&str, &str)> = vec![("key", ctx.key), ("name", &self.name)];
+
+        if self.exact {
             params.push(("exact", "yes"))
         }
 
-        Url::parse_with_params(LOOKUP_JSON_URL, params)
-            .unwrap()
-            .to_string()
+        ctx.url(LOOKUP_JSON_PATH, params)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        validate_name(&self.name)
     }
 }
 
-pub fn lookup(name: &str) -> impl FnOnce(&str) -> String + '_ {
-    _lookup(name, false)
+pub fn lookup(name: &str) -> LookupRequest {
+    LookupRequest::new(name)
 }
 
-pub fn lookup_exact(name: &str) -> impl FnOnce(&str) -> String + '_ {
-    _lookup(name, true)
+pub fn lookup_exact(name: &str) -> LookupRequest {
+    LookupRequest::new(name).exact()
 }
 
 #[cfg(test)]
@@ -31,7 +58,7 @@ mod tests {
     fn test_lookup_exact() {
         let req = lookup_exact("Angus");
         assert_eq!(
-            req("asdf"),
+            req.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/lookup.json?key=asdf&name=Angus&exact=yes"
         );
     }
@@ -40,8 +67,47 @@ mod tests {
     fn test_lookup() {
         let req = lookup("Angus");
         assert_eq!(
-            req("asdf"),
+            req.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/lookup.json?key=asdf&name=Angus"
         );
     }
+
+    #[test]
+    fn test_lookup_request_builder() {
+        let req = LookupRequest::new("Angus").exact();
+        assert_eq!(req, lookup_exact("Angus"));
+        assert_eq!(req.clone(), req);
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"name":"Angus","exact":true}"#
+        );
+    }
+
+    #[test]
+    fn test_lookup_validate() {
+        assert!(lookup("Angus").validate().is_ok());
+        for name in ["", "   ", "\t\n"] {
+            match lookup(name).validate() {
+                Err(Error::InvalidParameter { parameter, .. }) => assert_eq!(parameter, "name"),
+                r => panic!("{:?} should be rejected: {:?}", name, r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup_with_base_url() {
+        let req = lookup("Angus");
+        assert_eq!(
+            req.url(&RequestContext::with_base_url(
+                "asdf",
+                "http://localhost:8080/btn/"
+            ))
+            .unwrap(),
+            "http://localhost:8080/btn/api/lookup.json?key=asdf&name=Angus"
+        );
+        match req.url(&RequestContext::with_base_url("asdf", "not a url")) {
+            Err(Error::Url(_)) => (),
+            r => panic!("an unparseable base URL should be an error: {:?}", r),
+        }
+    }
 }
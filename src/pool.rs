@@ -0,0 +1,312 @@
+use crate::error::Error;
+use crate::limiter::QuotaStatus;
+use crate::request::Request;
+use crate::session::{Session, UsageLimit};
+use crate::transport::{ReqwestTransport, Transport};
+use crate::types::{RateLimited, RemoteError};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use RateLimited::*;
+
+#[derive(Debug, Clone, Default)]
+struct KeyState {
+    retired: Option<RemoteError>,
+    requests: u64,
+}
+
+/// Where one key of a `KeyPool` stands.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyReport {
+    pub key: String,
+    /// The error that took the key out of rotation, if any.
+    pub retired: Option<RemoteError>,
+    /// Requests the pool has sent with this key, whatever their outcome.
+    pub requests: u64,
+    pub quota: Vec<QuotaStatus>,
+}
+
+/// Spreads requests over several API keys, each with its own limiters.
+///
+/// Every request goes to the key with the most headroom, i.e. whose tightest
+/// limit has the largest share of its calls left. A key the server reports as invalid or
+/// exhausted is taken out of rotation and the request moves on to the next key.
+/// Clones share the same keys, limiters and bookkeeping.
+#[derive(Clone)]
+pub struct KeyPool<T = ReqwestTransport> {
+    sessions: Vec<Session<T>>,
+    states: Arc<Mutex<Vec<KeyState>>>,
+}
+
+impl KeyPool {
+    pub fn new<K: Into<String>>(
+        keys: impl IntoIterator<Item = K>,
+        usage_limit: &UsageLimit,
+    ) -> KeyPool {
+        KeyPool::with_transport(keys, usage_limit, ReqwestTransport::default())
+    }
+}
+
+impl<T: Transport + Clone> KeyPool<T> {
+    pub fn with_transport<K: Into<String>>(
+        keys: impl IntoIterator<Item = K>,
+        usage_limit: &UsageLimit,
+        transport: T,
+    ) -> KeyPool<T> {
+        KeyPool::from_sessions(
+            keys.into_iter()
+                .map(|key| Session::with_transport(key, usage_limit, transport.clone()))
+                .collect(),
+        )
+    }
+}
+
+impl<T: Transport> KeyPool<T> {
+    /// Pools sessions configured elsewhere, e.g. each with its own state file.
+    pub fn from_sessions(sessions: Vec<Session<T>>) -> KeyPool<T> {
+        KeyPool {
+            states: Arc::new(Mutex::new(vec![KeyState::default(); sessions.len()])),
+            sessions,
+        }
+    }
+
+    pub fn sessions(&self) -> &[Session<T>] {
+        &self.sessions
+    }
+
+    /// Puts a retired key back into rotation, e.g. after its allowance renewed.
+    pub fn restore_key(&self, key: &str) {
+        let mut states = self.states.lock().unwrap();
+        for (session, state) in self.sessions.iter().zip(states.iter_mut()) {
+            if session.key == key {
+                state.retired = None;
+            }
+        }
+    }
+
+    pub fn report(&self) -> Result<Vec<KeyReport>, Error> {
+        let states = self.states.lock().unwrap().clone();
+        self.sessions
+            .iter()
+            .zip(states)
+            .map(|(session, state)| {
+                Ok(KeyReport {
+                    key: session.key.clone(),
+                    retired: state.retired,
+                    requests: state.requests,
+                    quota: session.quota_status()?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn request<R: Request>(&self, req: R) -> RateLimited<R::Response, Error> {
+        self.request_with(|session| session.request(&req))
+    }
+
+    /// Like `request`, but waits for the chosen key's limiters as
+    /// `Session::request_wait` does.
+    pub fn request_wait<R: Request>(
+        &self,
+        req: R,
+        max_wait: Option<Duration>,
+    ) -> RateLimited<R::Response, Error> {
+        self.request_with(|session| session.request_wait(&req, max_wait))
+    }
+
+    /// Active keys, most headroom first.
+    fn rotation(&self) -> Vec<usize> {
+        let states = self.states.lock().unwrap();
+        let mut active: Vec<(usize, f64)> = self
+            .sessions
+            .iter()
+            .zip(states.iter())
+            .enumerate()
+            .filter(|(_, (_, state))| state.retired.is_none())
+            .map(|(i, (session, _))| (i, headroom(session)))
+            .collect();
+        active.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        active.into_iter().map(|(i, _)| i).collect()
+    }
+
+    fn request_with<S>(
+        &self,
+        mut send: impl FnMut(&Session<T>) -> RateLimited<S, Error>,
+    ) -> RateLimited<S, Error> {
+        let mut governed: Option<RateLimited<S, Error>> = None;
+        for i in self.rotation() {
+            let result = send(&self.sessions[i]);
+            match &result {
+                Governed(_, earliest) | TimedOut(_, earliest) => {
+                    let sooner = match &governed {
+                        Some(Governed(_, best)) | Some(TimedOut(_, best)) => {
+                            earliest.earliest_possible() < best.earliest_possible()
+                        }
+                        _ => true,
+                    };
+                    if sooner {
+                        governed = Some(result);
+                    }
+                    continue;
+                }
+                Failed(Error::InvalidParameter { .. }) => return result,
+                _ => (),
+            }
+            let mut states = self.states.lock().unwrap();
+            states[i].requests += 1;
+            match result {
+                Failed(Error::Remote(e)) if e.is_key_problem() => states[i].retired = Some(e),
+                result => return result,
+            }
+        }
+        governed.unwrap_or(Failed(Error::NoKeys))
+    }
+}
+
+/// The smallest share of calls left across a session's limits, from 0 to 1.
+/// Shares rather than counts, so that a nearly spent daily limit outweighs a
+/// per-second limit that is always low.
+fn headroom<T: Transport>(session: &Session<T>) -> f64 {
+    session
+        .quota_status()
+        .map(|status| {
+            status
+                .iter()
+                .map(|s| s.remaining as f64 / s.limit as f64)
+                .fold(1.0, f64::min)
+        })
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limiter::{Limit, RateLimiters, Window};
+    use crate::lookup::lookup;
+    use crate::transport::FakeTransport;
+    use http::StatusCode;
+    use nonzero_ext::nonzero;
+
+    fn pool(keys: &[&str], transport: FakeTransport) -> KeyPool<FakeTransport> {
+        KeyPool::with_transport(
+            keys.iter().copied(),
+            &UsageLimit::test_limits(10, 3, 100, 100),
+            transport,
+        )
+    }
+
+    fn keys_used(transport: &FakeTransport) -> Vec<String> {
+        transport
+            .requests()
+            .iter()
+            .map(|url| {
+                url.split("key=")
+                    .nth(1)
+                    .unwrap()
+                    .split('&')
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pool_spreads_by_headroom() {
+        let transport = FakeTransport::new();
+        for _ in 0..6 {
+            transport.push_response(StatusCode::OK, "[]");
+        }
+        let pool = pool(&["a", "b"], transport.clone());
+        for _ in 0..6 {
+            match pool.request(lookup("Jordan")) {
+                Allowed(_) => (),
+                r => panic!("expected the request to be allowed: {:?}", r),
+            }
+        }
+        assert_eq!(keys_used(&transport), vec!["a", "b", "a", "b", "a", "b"]);
+        match pool.request(lookup("Jordan")) {
+            Governed(i, _) => assert_eq!(i, "Hour"),
+            r => panic!("every key should be out of hourly quota: {:?}", r),
+        }
+        let report = pool.report().unwrap();
+        assert_eq!(report[0].requests, 3);
+        assert_eq!(report[1].requests, 3);
+        assert_eq!(report[1].quota[1].remaining, 0);
+    }
+
+    #[test]
+    fn test_pool_prefers_unspent_daily_quota() {
+        let path =
+            std::env::temp_dir().join(format!("behindthename-pool-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let daily = UsageLimit::new(vec![Limit::new(nonzero!(50u64), Window::Day)]);
+        let spent = RateLimiters::with_state_file(daily, &path).unwrap();
+        for _ in 0..45 {
+            spent.check(1.0).unwrap();
+        }
+
+        let transport = FakeTransport::new().with_response(StatusCode::OK, "[]");
+        let usage_limit = UsageLimit::test_limits(2, 100, 50, 1000);
+        let pool = KeyPool::from_sessions(vec![
+            Session::with_transport("a", &usage_limit, transport.clone())
+                .with_state_file(&path)
+                .unwrap(),
+            Session::with_transport("b", &usage_limit, transport.clone()),
+        ]);
+        assert!(pool.request(lookup("Jordan")).into_result().is_ok());
+        assert_eq!(keys_used(&transport), vec!["b"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pool_retires_bad_keys() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, r#"{"error_code":1,"error":"invalid key"}"#)
+            .with_response(StatusCode::OK, "[]")
+            .with_response(StatusCode::OK, r#"{"error_code":50,"error":"not found"}"#)
+            .with_response(
+                StatusCode::OK,
+                r#"{"error_code":3,"error":"limit exceeded"}"#,
+            );
+        let pool = pool(&["a", "b"], transport.clone());
+
+        match pool.request(lookup("Jordan")) {
+            Allowed(_) => (),
+            r => panic!("the request should move on to the second key: {:?}", r),
+        }
+        match pool.request(lookup("Qwzx")) {
+            Failed(Error::Remote(e)) => assert_eq!(e.error_code, 50),
+            r => panic!("a missing name is not a key problem: {:?}", r),
+        }
+        match pool.request(lookup("Jordan")) {
+            Failed(Error::NoKeys) => (),
+            r => panic!("both keys should be retired: {:?}", r),
+        }
+        assert_eq!(keys_used(&transport), vec!["a", "b", "b", "b"]);
+
+        let report = pool.report().unwrap();
+        assert_eq!(report[0].retired.as_ref().unwrap().error_code, 1);
+        assert_eq!(report[1].retired.as_ref().unwrap().error_code, 3);
+        assert_eq!(report[1].requests, 3);
+
+        pool.restore_key("a");
+        transport.push_response(StatusCode::OK, "[]");
+        match pool.request(lookup("Jordan")) {
+            Allowed(_) => (),
+            r => panic!("the restored key should be used: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_pool_rejects_invalid_requests_once() {
+        let transport = FakeTransport::new();
+        let pool = pool(&["a", "b"], transport.clone());
+        match pool.request(lookup("")) {
+            Failed(Error::InvalidParameter { .. }) => (),
+            r => panic!("expected a local validation error: {:?}", r),
+        }
+        assert!(transport.requests().is_empty());
+        assert_eq!(pool.report().unwrap()[0].requests, 0);
+    }
+}
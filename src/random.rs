@@ -1,22 +1,86 @@
-use crate::constants::RANDOM_JSON_URL;
-use crate::types::{Gender, Gender::*};
-use url::Url;
+use crate::constants::RANDOM_JSON_PATH;
+use crate::error::Error;
+use crate::request::{parse_usage, validate_usage, Request, RequestContext};
+use crate::session::Session;
+use crate::transport::Transport;
+use crate::types::{Gender, Gender::*, JsonNameList, Partial};
+use crate::usage::{IntoUsageCode, UnknownUsageCode, UsageCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
 
-pub fn random_with_params(
-    gender: Gender,
-    usage: Option<&str>,
-    number: Option<u8>,
-    random_surname: bool,
-) -> impl FnOnce(&str) -> String + '_ {
-    move |key| {
-        let mut params: Vec<(&str, &str)> = vec![("key", key)];
+/// The most names a single call to the random endpoint will return.
+pub const MAX_RANDOM_NUMBER: u8 = 6;
+
+/// How many calls in a row may add no new names before `random_many` gives up.
+const MAX_STALLED_CALLS: usize = 5;
 
-        if let Some(u) = usage {
-            params.push(("usage", u))
+/// How long a `random_many` call waits for the limiters when no `max_wait` is
+/// given: long enough for a per-second limit, not for an exhausted longer one.
+pub const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RandomRequest {
+    pub gender: Gender,
+    pub usage: Option<UsageCode>,
+    pub number: Option<u8>,
+    pub random_surname: bool,
+    #[serde(skip)]
+    unknown_usage: Option<UnknownUsageCode>,
+}
+
+impl Default for RandomRequest {
+    fn default() -> Self {
+        RandomRequest {
+            gender: Any,
+            usage: None,
+            number: None,
+            random_surname: false,
+            unknown_usage: None,
+        }
+    }
+}
+
+impl RandomRequest {
+    pub fn new() -> RandomRequest {
+        RandomRequest::default()
+    }
+
+    pub fn gender(mut self, gender: Gender) -> Self {
+        self.gender = gender;
+        self
+    }
+
+    /// Sets the usage. A string outside the catalog makes `validate` fail;
+    /// use `UsageCode::Other` to send it anyway.
+    pub fn usage(mut self, usage: impl IntoUsageCode) -> Self {
+        (self.usage, self.unknown_usage) = parse_usage(usage);
+        self
+    }
+
+    pub fn number(mut self, number: u8) -> Self {
+        self.number = Some(number);
+        self
+    }
+
+    pub fn with_surname(mut self) -> Self {
+        self.random_surname = true;
+        self
+    }
+}
+
+impl Request for RandomRequest {
+    type Response = JsonNameList;
+
+    fn url(&self, ctx: &RequestContext) -> Result<String, Error> {
+        let mut params: Vec<(&str, &str)> = vec![("key", ctx.key)];
+
+        if let Some(u) = &self.usage {
+            params.push(("usage", u.code()))
         }
 
         let gstring: String;
-        match gender {
+        match self.gender {
             Any => (),
             Ambiguous => {
                 // This is needed because behindthename's "random" API only recognizes 'u' as the ambiguous/neutral key
@@ -30,46 +94,141 @@ pub fn random_with_params(
         }
 
         let nstring: String;
-        if let Some(n) = number {
+        if let Some(n) = self.number {
             nstring = n.to_string();
             params.push(("number", &nstring))
         }
 
-        if random_surname {
+        if self.random_surname {
             params.push(("randomsurname", "yes"))
         }
 
-        Url::parse_with_params(RANDOM_JSON_URL, params)
-            .unwrap()
-            .to_string()
+        ctx.url(RANDOM_JSON_PATH, params)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if let Some(n) = self.number {
+            if !(1..=MAX_RANDOM_NUMBER).contains(&n) {
+                return Err(Error::InvalidParameter {
+                    parameter: "number",
+                    reason: format!("{} is outside 1..={}", n, MAX_RANDOM_NUMBER),
+                });
+            }
+        }
+        validate_usage(&self.unknown_usage)
     }
 }
 
-pub fn random() -> impl FnOnce(&str) -> String {
+/// The names gathered by `Session::random_many`, in the order they were first seen.
+pub type RandomNames = Partial<String>;
+
+impl<T: Transport> Session<T> {
+    /// Collects `n` distinct random names matching `filters`, splitting the count
+    /// into as many calls of up to `MAX_RANDOM_NUMBER` as needed. `filters.number`
+    /// is ignored, and `filters.random_surname` is rejected, since the surname
+    /// would come back mixed in with the first names.
+    ///
+    /// Each call waits for the limiters as `request_wait` does, with `max_wait`
+    /// applying per call, or `DEFAULT_MAX_WAIT` if it is `None`, so that a spent
+    /// hourly, daily or yearly quota ends the fan-out rather than blocking it.
+    /// When a call is governed, times out or fails, the names
+    /// gathered so far are returned with the error in `stopped`. The fan-out also
+    /// ends, without an error, when several calls in a row bring no new names,
+    /// as happens for usages with only a handful of names.
+    pub fn random_many(
+        &self,
+        n: usize,
+        filters: &RandomRequest,
+        max_wait: Option<Duration>,
+    ) -> RandomNames {
+        let mut seen = HashSet::new();
+        let mut result = RandomNames::new(n);
+        if filters.random_surname {
+            result.stopped = Some(Error::InvalidParameter {
+                parameter: "random_surname",
+                reason: "random_many only collects first names".to_string(),
+            });
+            return result;
+        }
+        let max_wait = Some(max_wait.unwrap_or(DEFAULT_MAX_WAIT));
+        let mut stalled = 0;
+        while result.names.len() < n && stalled < MAX_STALLED_CALLS {
+            let remaining = n - result.names.len();
+            let req = RandomRequest {
+                number: Some(remaining.min(MAX_RANDOM_NUMBER as usize) as u8),
+                ..filters.clone()
+            };
+            match self.request_wait(req, max_wait).into_result() {
+                Ok(JsonNameList { names }) => {
+                    let before = result.names.len();
+                    for name in names {
+                        if result.names.len() < n && seen.insert(name.clone()) {
+                            result.names.push(name);
+                        }
+                    }
+                    if result.names.len() == before {
+                        stalled += 1;
+                    } else {
+                        stalled = 0;
+                    }
+                }
+                Err(e) => {
+                    result.stopped = Some(e);
+                    break;
+                }
+            }
+        }
+        result
+    }
+}
+
+pub fn random_with_params(
+    gender: Gender,
+    usage: Option<&str>,
+    number: Option<u8>,
+    random_surname: bool,
+) -> RandomRequest {
+    let mut request = RandomRequest {
+        gender,
+        number,
+        random_surname,
+        ..RandomRequest::default()
+    };
+    (request.usage, request.unknown_usage) = match usage {
+        Some(usage) => parse_usage(usage),
+        None => (None, None),
+    };
+    request
+}
+
+pub fn random() -> RandomRequest {
     random_with_params(Any, None, None, false)
 }
 
-pub fn random_with_surname() -> impl FnOnce(&str) -> String {
+pub fn random_with_surname() -> RandomRequest {
     random_with_params(Any, None, None, true)
 }
 
-pub fn random_with_gender(gender: Gender) -> impl FnOnce(&str) -> String {
+pub fn random_with_gender(gender: Gender) -> RandomRequest {
     random_with_params(gender, None, None, false)
 }
 
-pub fn random_with_usage(usage: &str) -> impl FnOnce(&str) -> String + '_ {
+pub fn random_with_usage(usage: &str) -> RandomRequest {
     random_with_params(Any, Some(usage), None, false)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::session::UsageLimit;
+    use crate::transport::FakeTransport;
+    use http::StatusCode;
 
     #[test]
     fn test_random() {
         let req = random();
         assert_eq!(
-            req("asdf"),
+            req.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/random.json?key=asdf"
         );
     }
@@ -78,7 +237,7 @@ mod tests {
     fn test_random_with_surname() {
         let req = random_with_surname();
         assert_eq!(
-            req("asdf"),
+            req.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/random.json?key=asdf&randomsurname=yes"
         );
     }
@@ -87,27 +246,27 @@ mod tests {
     fn test_random_with_gender() {
         let req_male = random_with_gender(Male);
         assert_eq!(
-            req_male("asdf"),
+            req_male.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/random.json?key=asdf&gender=m"
         );
         let req_female = random_with_gender(Female);
         assert_eq!(
-            req_female("asdf"),
+            req_female.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/random.json?key=asdf&gender=f"
         );
         let req_neutral = random_with_gender(Neutral);
         assert_eq!(
-            req_neutral("asdf"),
+            req_neutral.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/random.json?key=asdf&gender=u"
         );
         let req_ambiguous = random_with_gender(Ambiguous);
         assert_eq!(
-            req_ambiguous("asdf"),
+            req_ambiguous.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/random.json?key=asdf&gender=u"
         );
         let req_any = random_with_gender(Any);
         assert_eq!(
-            req_any("asdf"),
+            req_any.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/random.json?key=asdf"
         );
     }
@@ -116,7 +275,7 @@ mod tests {
     fn test_random_with_usage() {
         let req = random_with_usage("eng");
         assert_eq!(
-            req("asdf"),
+            req.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/random.json?key=asdf&usage=eng"
         );
     }
@@ -124,6 +283,156 @@ mod tests {
     #[test]
     fn test_random_with_params() {
         let req = random_with_params(Gender::Female, Some("ita"), Some(5), true);
-        assert_eq!(req("asdf"), "https://www.behindthename.com/api/random.json?key=asdf&usage=ita&gender=f&number=5&randomsurname=yes");
+        assert_eq!(req.url(&RequestContext::new("asdf")).unwrap(), "https://www.behindthename.com/api/random.json?key=asdf&usage=ita&gender=f&number=5&randomsurname=yes");
+    }
+
+    #[test]
+    fn test_random_request_with_usage_code() {
+        let req = RandomRequest::new().usage(UsageCode::Italian);
+        assert_eq!(req, random_with_usage("ita"));
+    }
+
+    #[test]
+    fn test_random_validate() {
+        assert!(random().validate().is_ok());
+        assert!(RandomRequest::new().number(1).validate().is_ok());
+        assert!(RandomRequest::new()
+            .number(MAX_RANDOM_NUMBER)
+            .usage("eng")
+            .validate()
+            .is_ok());
+        for number in [0, MAX_RANDOM_NUMBER + 1, 200] {
+            match RandomRequest::new().number(number).validate() {
+                Err(Error::InvalidParameter { parameter, .. }) => assert_eq!(parameter, "number"),
+                r => panic!("{} should be rejected: {:?}", number, r),
+            }
+        }
+        match random_with_usage("engl").validate() {
+            Err(e) => assert_eq!(e.to_string(), "invalid usage: unknown usage code \"engl\""),
+            r => panic!("\"engl\" should be rejected: {:?}", r),
+        }
+        assert!(RandomRequest::new().usage("xyz").validate().is_err());
+    }
+
+    #[test]
+    fn test_random_with_unlisted_usage() {
+        assert!("xyz".parse::<UsageCode>().is_err());
+        let transport = FakeTransport::new().with_response(StatusCode::OK, r#"{"names":["A"]}"#);
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        let req = RandomRequest::new().usage(UsageCode::Other("xyz".to_string()));
+        assert!(req.validate().is_ok());
+        assert!(sesh.request(req).into_result().is_ok());
+        assert_eq!(
+            sesh.transport().requests(),
+            vec!["https://www.behindthename.com/api/random.json?key=some_key&usage=xyz"]
+        );
+    }
+
+    #[test]
+    fn test_random_request_builder() {
+        let req = RandomRequest::new()
+            .gender(Female)
+            .usage("ita")
+            .number(5)
+            .with_surname();
+        assert_eq!(req, random_with_params(Female, Some("ita"), Some(5), true));
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"gender":"f","usage":"ita","number":5,"random_surname":true}"#
+        );
+    }
+
+    #[test]
+    fn test_random_many_fans_out_and_dedups() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, r#"{"names":["A","B","C","D","E","F"]}"#)
+            .with_response(StatusCode::OK, r#"{"names":["A","G","H","I","J","K"]}"#)
+            .with_response(StatusCode::OK, r#"{"names":["L","M"]}"#);
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        let result = sesh.random_many(13, &RandomRequest::new().usage("ita"), None);
+        assert!(result.is_complete());
+        assert!(result.stopped.is_none());
+        assert_eq!(
+            result.names,
+            vec!["A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M"]
+        );
+        assert_eq!(
+            sesh.transport().requests(),
+            vec![
+                "https://www.behindthename.com/api/random.json?key=some_key&usage=ita&number=6",
+                "https://www.behindthename.com/api/random.json?key=some_key&usage=ita&number=6",
+                "https://www.behindthename.com/api/random.json?key=some_key&usage=ita&number=2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_random_many_rejects_surnames() {
+        let transport = FakeTransport::new();
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        let result = sesh.random_many(3, &random_with_surname(), None);
+        assert!(result.names.is_empty());
+        match result.stopped {
+            Some(Error::InvalidParameter { parameter, .. }) => {
+                assert_eq!(parameter, "random_surname")
+            }
+            r => panic!("random_surname should be rejected: {:?}", r),
+        }
+        assert!(sesh.transport().requests().is_empty());
+    }
+
+    #[test]
+    fn test_random_many_stops_when_quota_runs_out() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, r#"{"names":["A","B","C","D","E","F"]}"#)
+            .with_response(StatusCode::OK, r#"{"names":["G","H","I","J","K","L"]}"#);
+        let usage_limit = UsageLimit::test_limits(10, 1, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        let result = sesh.random_many(12, &random(), Some(Duration::from_millis(10)));
+        assert!(!result.is_complete());
+        assert_eq!(result.names, vec!["A", "B", "C", "D", "E", "F"]);
+        match result.stopped {
+            Some(Error::TimedOut { interval, .. }) => assert_eq!(interval, "Hour"),
+            r => panic!("expected the hourly limiter to stop the fan-out: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_random_many_does_not_wait_out_long_limits() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, r#"{"names":["A","B","C","D","E","F"]}"#);
+        let usage_limit = UsageLimit::test_limits(10, 10, 1, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        let started = std::time::Instant::now();
+        let result = sesh.random_many(12, &random(), None);
+        assert!(started.elapsed() < DEFAULT_MAX_WAIT);
+        assert_eq!(result.names.len(), 6);
+        match result.stopped {
+            Some(Error::TimedOut { interval, .. }) => assert_eq!(interval, "Day"),
+            r => panic!("expected the daily limiter to stop the fan-out: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_random_many_gives_up_without_new_names() {
+        let transport = FakeTransport::new();
+        for _ in 0..10 {
+            transport.push_response(StatusCode::OK, r#"{"names":["A"]}"#);
+        }
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        let result = sesh.random_many(3, &random(), None);
+        assert_eq!(result.names, vec!["A"]);
+        assert!(result.stopped.is_none());
+        assert_eq!(sesh.transport().requests().len(), 1 + MAX_STALLED_CALLS);
     }
 }
@@ -0,0 +1,134 @@
+use crate::error::Error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current version of the `Record` storage format.
+pub const RECORD_VERSION: u32 = 1;
+
+/// A response stored together with the request that produced it.
+///
+/// Version 1 of the format is a JSON object with four fields:
+///
+/// * `version`: always `1`;
+/// * `timestamp`: when the response was received, in whole seconds since the Unix epoch;
+/// * `request`: the request struct's fields, e.g. `{"name":"Jordan","exact":false}`
+///   for a `LookupRequest`;
+/// * `response`: the response exactly as the API shapes it, e.g. the array of
+///   name details for a lookup or `{"names":[...]}` for a random or related call.
+///
+/// Readers should reject records whose `version` they do not know, which
+/// `Record::from_json` does.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Record<Q, R> {
+    pub version: u32,
+    pub timestamp: u64,
+    pub request: Q,
+    pub response: R,
+}
+
+impl<Q, R> Record<Q, R> {
+    /// Records `response` to `request`, timestamped now.
+    pub fn new(request: Q, response: R) -> Record<Q, R> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Record::with_timestamp(request, response, timestamp)
+    }
+
+    pub fn with_timestamp(request: Q, response: R, timestamp: u64) -> Record<Q, R> {
+        Record {
+            version: RECORD_VERSION,
+            timestamp,
+            request,
+            response,
+        }
+    }
+}
+
+impl<Q: Serialize, R: Serialize> Record<Q, R> {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
+
+/// Just the `version` field, read before the rest of a record so that a record
+/// of another version is rejected whatever its shape.
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: u32,
+}
+
+impl<Q: DeserializeOwned, R: DeserializeOwned> Record<Q, R> {
+    pub fn from_json(json: &str) -> Result<Record<Q, R>, Error> {
+        let decode_error = |source| Error::Decode {
+            body: json.to_string(),
+            source,
+        };
+        let probe: VersionProbe = serde_json::from_str(json).map_err(decode_error)?;
+        if probe.version != RECORD_VERSION {
+            return Err(Error::UnsupportedRecordVersion(probe.version));
+        }
+        serde_json::from_str(json).map_err(decode_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lookup::{lookup, LookupRequest};
+    use crate::random::{random_with_params, RandomRequest};
+    use crate::types::{Gender, JsonNameDetails, JsonNameList, JsonResponse};
+
+    #[test]
+    fn test_record_format() {
+        let response: JsonNameDetails = serde_json::from_str(
+            r#"[{"name":"Jordan","gender":"mf","usages":[{"usage_code":"eng","usage_full":"English","usage_gender":"mf"}]}]"#,
+        )
+        .unwrap();
+        let record = Record::with_timestamp(lookup("Jordan"), response, 1_700_000_000);
+        let json = record.to_json().unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":1,"timestamp":1700000000,"request":{"name":"Jordan","exact":false},"response":[{"name":"Jordan","gender":"mf","usages":[{"usage_code":"eng","usage_full":"English","usage_gender":"mf"}]}]}"#
+        );
+        assert_eq!(
+            Record::<LookupRequest, JsonNameDetails>::from_json(&json).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn test_record_round_trip_any_gender() {
+        let record = Record::new(
+            random_with_params(Gender::Any, None, Some(2), false),
+            JsonResponse::NameList(JsonNameList {
+                names: vec!["Giulia".to_string(), "Marco".to_string()],
+            }),
+        );
+        let json = record.to_json().unwrap();
+        assert_eq!(
+            Record::<RandomRequest, JsonResponse>::from_json(&json).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn test_record_rejects_unknown_version() {
+        let json = r#"{"version":2,"timestamp":0,"request":{"name":"Jordan","exact":false},"response":[]}"#;
+        match Record::<LookupRequest, JsonNameDetails>::from_json(json) {
+            Err(Error::UnsupportedRecordVersion(2)) => (),
+            r => panic!("expected an unsupported version error: {:?}", r),
+        }
+        let json =
+            r#"{"version":3,"recorded_at":"2030-01-01T00:00:00Z","exchange":{"query":"Jordan"}}"#;
+        match Record::<LookupRequest, JsonNameDetails>::from_json(json) {
+            Err(Error::UnsupportedRecordVersion(3)) => (),
+            r => panic!(
+                "a record of another shape should still fail on its version: {:?}",
+                r
+            ),
+        }
+    }
+}
@@ -1,21 +1,54 @@
-use crate::constants::RELATED_JSON_URL;
-use crate::types::{Gender, Gender::*};
-use url::Url;
-
-pub fn related_with_params<'a>(
-    name: &'a str,
-    usage: Option<&'a str>,
-    gender: Gender,
-) -> impl FnOnce(&str) -> String + 'a {
-    move |key| {
-        let mut params: Vec<(&str, &str)> = vec![("key", key), ("name", name)];
-
-        if let Some(u) = usage {
-            params.push(("usage", u))
+use crate::constants::RELATED_JSON_PATH;
+use crate::error::Error;
+use crate::request::{parse_usage, validate_name, validate_usage, Request, RequestContext};
+use crate::types::{Gender, Gender::*, JsonNameList};
+use crate::usage::{IntoUsageCode, UnknownUsageCode, UsageCode};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RelatedRequest {
+    pub name: String,
+    pub usage: Option<UsageCode>,
+    pub gender: Gender,
+    #[serde(skip)]
+    unknown_usage: Option<UnknownUsageCode>,
+}
+
+impl RelatedRequest {
+    pub fn new(name: impl Into<String>) -> RelatedRequest {
+        RelatedRequest {
+            name: name.into(),
+            usage: None,
+            gender: Any,
+            unknown_usage: None,
+        }
+    }
+
+    /// Sets the usage. A string outside the catalog makes `validate` fail;
+    /// use `UsageCode::Other` to send it anyway.
+    pub fn usage(mut self, usage: impl IntoUsageCode) -> Self {
+        (self.usage, self.unknown_usage) = parse_usage(usage);
+        self
+    }
+
+    pub fn gender(mut self, gender: Gender) -> Self {
+        self.gender = gender;
+        self
+    }
+}
+
+impl Request for RelatedRequest {
+    type Response = JsonNameList;
+
+    fn url(&self, ctx: &RequestContext) -> Result<String, Error> {
+        let mut params: Vec<(&str, &str)> = vec![("key", ctx.key), ("name", &self.name)];
+
+        if let Some(u) = &self.usage {
+            params.push(("usage", u.code()))
         }
 
         let gstring: String;
-        match gender {
+        match self.gender {
             Any => (),
             Neutral => {
                 // This is needed because behindthename's "related" API only recognizes 'mf' as the ambiguous/neutral key
@@ -28,21 +61,32 @@ pub fn related_with_params<'a>(
             }
         }
 
-        Url::parse_with_params(RELATED_JSON_URL, params)
-            .unwrap()
-            .to_string()
+        ctx.url(RELATED_JSON_PATH, params)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        validate_name(&self.name)?;
+        validate_usage(&self.unknown_usage)
+    }
+}
+
+pub fn related_with_params(name: &str, usage: Option<&str>, gender: Gender) -> RelatedRequest {
+    let request = RelatedRequest::new(name).gender(gender);
+    match usage {
+        Some(usage) => request.usage(usage),
+        None => request,
     }
 }
 
-pub fn related(name: &str) -> impl FnOnce(&str) -> String + '_ {
+pub fn related(name: &str) -> RelatedRequest {
     related_with_params(name, None, Any)
 }
 
-pub fn related_with_usage<'a>(name: &'a str, usage: &'a str) -> impl FnOnce(&str) -> String + 'a {
+pub fn related_with_usage(name: &str, usage: &str) -> RelatedRequest {
     related_with_params(name, Some(usage), Any)
 }
 
-pub fn related_with_gender(name: &str, gender: Gender) -> impl FnOnce(&str) -> String + '_ {
+pub fn related_with_gender(name: &str, gender: Gender) -> RelatedRequest {
     related_with_params(name, None, gender)
 }
 
@@ -54,7 +98,7 @@ mod tests {
     fn test_related() {
         let req = related("Richard");
         assert_eq!(
-            req("asdf"),
+            req.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/related.json?key=asdf&name=Richard"
         );
     }
@@ -63,7 +107,7 @@ mod tests {
     fn test_related_with_usage() {
         let req = related_with_usage("Rebecca", "eng");
         assert_eq!(
-            req("asdf"),
+            req.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/related.json?key=asdf&name=Rebecca&usage=eng"
         );
     }
@@ -72,27 +116,27 @@ mod tests {
     fn test_related_with_gender() {
         let req_male = related_with_gender("Jordan", Male);
         assert_eq!(
-            req_male("asdf"),
+            req_male.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/related.json?key=asdf&name=Jordan&gender=m"
         );
         let req_female = related_with_gender("Jordan", Female);
         assert_eq!(
-            req_female("asdf"),
+            req_female.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/related.json?key=asdf&name=Jordan&gender=f"
         );
         let req_neutral = related_with_gender("Jordan", Neutral);
         assert_eq!(
-            req_neutral("asdf"),
+            req_neutral.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/related.json?key=asdf&name=Jordan&gender=mf"
         );
         let req_ambiguous = related_with_gender("Jordan", Ambiguous);
         assert_eq!(
-            req_ambiguous("asdf"),
+            req_ambiguous.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/related.json?key=asdf&name=Jordan&gender=mf"
         );
         let req_any = related_with_gender("Jordan", Any);
         assert_eq!(
-            req_any("asdf"),
+            req_any.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/related.json?key=asdf&name=Jordan"
         );
     }
@@ -101,8 +145,40 @@ mod tests {
     fn test_related_with_params() {
         let req = related_with_params("Sasha", Some("rus"), Male);
         assert_eq!(
-            req("asdf"),
+            req.url(&RequestContext::new("asdf")).unwrap(),
             "https://www.behindthename.com/api/related.json?key=asdf&name=Sasha&usage=rus&gender=m"
         );
     }
+
+    #[test]
+    fn test_related_validate() {
+        assert!(related_with_usage("Rebecca", "eng").validate().is_ok());
+        assert!(related(" ").validate().is_err());
+        for usage in ["engl", "xx", "", "e.n.g"] {
+            match related_with_usage("Rebecca", usage).validate() {
+                Err(Error::InvalidParameter { parameter, .. }) => assert_eq!(parameter, "usage"),
+                r => panic!("{:?} should be rejected: {:?}", usage, r),
+            }
+        }
+        assert!(related("Rebecca")
+            .usage(UsageCode::Other("xx".to_string()))
+            .validate()
+            .is_ok());
+        assert!(related_with_usage("Rebecca", "engl")
+            .usage("eng")
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_related_request_builder() {
+        let req = RelatedRequest::new("Sasha")
+            .usage(UsageCode::Russian)
+            .gender(Male);
+        assert_eq!(req, related_with_params("Sasha", Some("rus"), Male));
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"name":"Sasha","usage":"rus","gender":"m"}"#
+        );
+    }
 }
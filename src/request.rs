@@ -0,0 +1,179 @@
+use crate::constants::DEFAULT_BASE_URL;
+use crate::error::Error;
+use crate::usage::{IntoUsageCode, UnknownUsageCode, UsageCode};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::marker::PhantomData;
+use url::Url;
+
+/// Everything a request builder needs to turn itself into a URL: the API key
+/// and the base URL the endpoint paths are resolved against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestContext<'a> {
+    pub key: &'a str,
+    pub base_url: &'a str,
+}
+
+impl RequestContext<'_> {
+    pub fn new(key: &str) -> RequestContext<'_> {
+        RequestContext {
+            key,
+            base_url: DEFAULT_BASE_URL,
+        }
+    }
+
+    pub fn with_base_url<'a>(key: &'a str, base_url: &'a str) -> RequestContext<'a> {
+        RequestContext { key, base_url }
+    }
+
+    pub(crate) fn url(&self, path: &str, params: Vec<(&str, &str)>) -> Result<String, Error> {
+        let mut url = parse_base_url(self.base_url)?.join(path)?;
+        url.query_pairs_mut().extend_pairs(params);
+        Ok(url.to_string())
+    }
+}
+
+/// A call to one API endpoint, typed by the response it produces.
+pub trait Request {
+    type Response: DeserializeOwned;
+
+    /// Fails if the context's base URL is not an http(s) URL.
+    fn url(&self, ctx: &RequestContext) -> Result<String, Error>;
+
+    /// Checks the parameters locally; `Session` calls this before spending quota.
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T: Request + ?Sized> Request for &T {
+    type Response = T::Response;
+
+    fn url(&self, ctx: &RequestContext) -> Result<String, Error> {
+        (**self).url(ctx)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        (**self).validate()
+    }
+}
+
+/// Wraps a URL-building closure with the response type it is expected to produce.
+pub struct Endpoint<F, R> {
+    build: F,
+    response: PhantomData<fn() -> R>,
+}
+
+impl<F, R> Endpoint<F, R>
+where
+    F: Fn(&RequestContext) -> Result<String, Error>,
+    R: DeserializeOwned,
+{
+    pub fn new(build: F) -> Endpoint<F, R> {
+        Endpoint {
+            build,
+            response: PhantomData,
+        }
+    }
+}
+
+impl<F, R> Request for Endpoint<F, R>
+where
+    F: Fn(&RequestContext) -> Result<String, Error>,
+    R: DeserializeOwned,
+{
+    type Response = R;
+
+    fn url(&self, ctx: &RequestContext) -> Result<String, Error> {
+        (self.build)(ctx)
+    }
+}
+
+impl<F, R> fmt::Debug for Endpoint<F, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Endpoint<{}>", std::any::type_name::<R>())
+    }
+}
+
+pub(crate) fn validate_name(name: &str) -> Result<(), Error> {
+    if name.trim().is_empty() {
+        return Err(Error::InvalidParameter {
+            parameter: "name",
+            reason: format!("{:?} is empty or only whitespace", name),
+        });
+    }
+    Ok(())
+}
+
+/// Splits a builder's usage argument into the code to send and, for strings
+/// outside the catalog, the error `validate` reports.
+pub(crate) fn parse_usage(
+    usage: impl IntoUsageCode,
+) -> (Option<UsageCode>, Option<UnknownUsageCode>) {
+    match usage.into_usage_code() {
+        Ok(usage) => (Some(usage), None),
+        Err(e) => (None, Some(e)),
+    }
+}
+
+pub(crate) fn validate_usage(unknown: &Option<UnknownUsageCode>) -> Result<(), Error> {
+    match unknown {
+        Some(e) => Err(Error::InvalidParameter {
+            parameter: "usage",
+            reason: e.to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Parses `base_url`, which must be an http(s) URL that endpoint paths can be
+/// resolved against.
+fn parse_base_url(base_url: &str) -> Result<Url, Error> {
+    let url = Url::parse(base_url)?;
+    if url.cannot_be_a_base() || !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::InvalidParameter {
+            parameter: "base_url",
+            reason: format!("{:?} is not an http(s) URL", base_url),
+        });
+    }
+    Ok(url)
+}
+
+/// Parses `base_url` and gives it a trailing slash, so that endpoint paths are
+/// appended to it rather than replacing its last segment.
+pub(crate) fn normalize_base_url(base_url: &str) -> Result<String, Error> {
+    let mut url = parse_base_url(base_url)?;
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_base_url() {
+        assert_eq!(
+            normalize_base_url("http://localhost:8080").unwrap(),
+            "http://localhost:8080/"
+        );
+        assert_eq!(
+            normalize_base_url("http://proxy.local/btn").unwrap(),
+            "http://proxy.local/btn/"
+        );
+        assert_eq!(
+            normalize_base_url("http://proxy.local/btn/").unwrap(),
+            "http://proxy.local/btn/"
+        );
+        assert!(normalize_base_url("not a url").is_err());
+        for base_url in ["mailto:foo", "data:text/plain,x", "ftp://example.com/"] {
+            match normalize_base_url(base_url) {
+                Err(Error::InvalidParameter { parameter, .. }) => assert_eq!(parameter, "base_url"),
+                r => panic!("{:?} should be rejected: {:?}", base_url, r),
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::error::Error;
+use crate::types::{RateLimited, RemoteErrorKind};
+use http::StatusCode;
+use rand::Rng;
+use std::time::Duration;
+
+/// How a `Session` retries calls that fail for transient reasons.
+///
+/// The delay before retry `n` is `backoff_base * 2^(n - 1)`, capped at
+/// `max_backoff`, and shortened by a random fraction of at most `jitter`.
+/// Every attempt goes through the session's limiters like any other call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff_base: Duration,
+    pub max_backoff: Duration,
+    pub jitter: f64,
+    pub retryable_codes: Vec<usize>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff_base: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            jitter: 0.5,
+            retryable_codes: vec![RemoteErrorKind::ServiceUnavailable.code()],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt and never retries.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn backoff_base(mut self, backoff_base: Duration) -> Self {
+        self.backoff_base = backoff_base;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn retryable_codes(mut self, retryable_codes: Vec<usize>) -> Self {
+        self.retryable_codes = retryable_codes;
+        self
+    }
+
+    pub(crate) fn should_retry<S>(&self, attempt: u32, result: &RateLimited<S, Error>) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        match result {
+            RateLimited::Failed(Error::Remote(e)) => self.retryable_codes.contains(&e.error_code),
+            RateLimited::Failed(Error::Status { status, .. }) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            RateLimited::Failed(Error::Transport(e)) => match e.downcast_ref::<reqwest::Error>() {
+                Some(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .backoff_base
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        if self.jitter > 0.0 {
+            delay.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..=self.jitter))
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RemoteError;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy = RetryPolicy::default()
+            .backoff_base(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(350))
+            .jitter(0.0);
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        assert_eq!(policy.backoff(40), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_backoff_jitter_stays_in_range() {
+        let policy = RetryPolicy::default()
+            .backoff_base(Duration::from_millis(100))
+            .jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.backoff(1);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::default();
+        let unavailable: RateLimited<(), Error> = RateLimited::Failed(Error::Remote(RemoteError {
+            error_code: 2,
+            error: "service not available".to_string(),
+        }));
+        let invalid_key: RateLimited<(), Error> = RateLimited::Failed(Error::Remote(RemoteError {
+            error_code: 1,
+            error: "invalid key".to_string(),
+        }));
+        let bad_gateway: RateLimited<(), Error> = RateLimited::Failed(Error::Status {
+            status: StatusCode::BAD_GATEWAY,
+            body: String::new(),
+        });
+        assert!(policy.should_retry(1, &unavailable));
+        assert!(!policy.should_retry(3, &unavailable));
+        assert!(!policy.should_retry(1, &invalid_key));
+        assert!(policy.should_retry(1, &bad_gateway));
+        assert!(!policy.should_retry(1, &RateLimited::<(), Error>::Allowed(())));
+        assert!(!RetryPolicy::none().should_retry(1, &unavailable));
+    }
+}
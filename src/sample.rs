@@ -0,0 +1,287 @@
+use crate::error::Error;
+use crate::random::RandomRequest;
+use crate::request::{parse_usage, validate_usage};
+use crate::session::Session;
+use crate::transport::Transport;
+use crate::types::{Gender, Partial};
+use crate::usage::{IntoUsageCode, UnknownUsageCode, UsageCode};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A random name together with the usage and gender it was requested for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TaggedName {
+    pub name: String,
+    pub usage: String,
+    pub gender: Gender,
+}
+
+/// Draws random names across several usages and genders in fixed proportions,
+/// e.g. 60% Italian and 40% English, half of each male and half female.
+///
+/// Weights are relative and need not sum to 1. Each (usage, gender) pair gets
+/// `n * usage_weight * gender_weight` names, rounded by the largest remainder
+/// method so that the counts add up to exactly `n`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WeightedSampler {
+    pub usages: Vec<(UsageCode, f64)>,
+    pub genders: Vec<(Gender, f64)>,
+    #[serde(skip)]
+    unknown_usages: Vec<UnknownUsageCode>,
+}
+
+/// The names gathered by `Session::sample`, grouped by (usage, gender) in the
+/// order the sampler lists them.
+pub type SampledNames = Partial<TaggedName>;
+
+impl WeightedSampler {
+    pub fn new() -> WeightedSampler {
+        WeightedSampler::default()
+    }
+
+    /// Adds a usage to the mix. A string outside the catalog makes `validate`
+    /// fail; use `UsageCode::Other` to sample it anyway.
+    pub fn usage(mut self, usage: impl IntoUsageCode, weight: f64) -> Self {
+        match parse_usage(usage) {
+            (Some(usage), _) => self.usages.push((usage, weight)),
+            (None, unknown) => self.unknown_usages.extend(unknown),
+        }
+        self
+    }
+
+    /// Adds a gender to the mix. Without any, every usage is drawn with `Gender::Any`.
+    pub fn gender(mut self, gender: Gender, weight: f64) -> Self {
+        self.genders.push((gender, weight));
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_usage(&self.unknown_usages.first().cloned())?;
+        if self.usages.is_empty() {
+            return Err(Error::InvalidParameter {
+                parameter: "usage",
+                reason: "no usages to sample from".to_string(),
+            });
+        }
+        for (_, weight) in &self.usages {
+            validate_weight("usage", *weight)?;
+        }
+        for (_, weight) in &self.genders {
+            validate_weight("gender", *weight)?;
+        }
+        Ok(())
+    }
+
+    /// Splits `n` across the (usage, gender) pairs. Pairs that get no names are
+    /// left out. Fails if the sampler does not pass `validate`.
+    pub fn allocate(&self, n: usize) -> Result<Vec<(RandomRequest, usize)>, Error> {
+        self.validate()?;
+        let genders = if self.genders.is_empty() {
+            vec![(Gender::Any, 1.0)]
+        } else {
+            self.genders.clone()
+        };
+        let cells: Vec<(RandomRequest, f64)> = self
+            .usages
+            .iter()
+            .flat_map(|(usage, uw)| {
+                genders.iter().map(move |(gender, gw)| {
+                    let req = RandomRequest::new().gender(*gender).usage(usage.clone());
+                    (req, uw * gw)
+                })
+            })
+            .collect();
+        let counts = largest_remainder(n, cells.iter().map(|(_, w)| *w));
+        Ok(cells
+            .into_iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|((req, _), count)| (req, count))
+            .collect())
+    }
+}
+
+fn validate_weight(parameter: &'static str, weight: f64) -> Result<(), Error> {
+    if weight.is_finite() && weight > 0.0 {
+        Ok(())
+    } else {
+        Err(Error::InvalidParameter {
+            parameter,
+            reason: format!("weight {} is not a positive number", weight),
+        })
+    }
+}
+
+fn largest_remainder(n: usize, weights: impl Iterator<Item = f64>) -> Vec<usize> {
+    let weights: Vec<f64> = weights.collect();
+    let total: f64 = weights.iter().sum();
+    if !(total.is_finite() && total > 0.0) {
+        return vec![0; weights.len()];
+    }
+    let shares: Vec<f64> = weights.iter().map(|w| n as f64 * w / total).collect();
+    let mut counts: Vec<usize> = shares.iter().map(|s| s.floor() as usize).collect();
+    let mut order: Vec<usize> = (0..shares.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (ra, rb) = (shares[a] - shares[a].floor(), shares[b] - shares[b].floor());
+        rb.total_cmp(&ra).then(a.cmp(&b))
+    });
+    let assigned: usize = counts.iter().sum();
+    for &i in order.iter().take(n.saturating_sub(assigned)) {
+        counts[i] += 1;
+    }
+    counts
+}
+
+impl<T: Transport> Session<T> {
+    /// Draws `n` names in the proportions set by `sampler`, calling `random_many`
+    /// once per (usage, gender) pair. Names are deduplicated within a pair but
+    /// not across pairs, so each keeps its share.
+    ///
+    /// Stops at the first pair whose calls fail or run out of quota, returning
+    /// what was gathered so far. A pair with fewer distinct names than its share
+    /// comes back short without stopping the others.
+    pub fn sample(
+        &self,
+        n: usize,
+        sampler: &WeightedSampler,
+        max_wait: Option<Duration>,
+    ) -> SampledNames {
+        let mut result = SampledNames::new(n);
+        let cells = match sampler.allocate(n) {
+            Ok(cells) => cells,
+            Err(e) => {
+                result.stopped = Some(e);
+                return result;
+            }
+        };
+        for (req, count) in cells {
+            let batch = self.random_many(count, &req, max_wait);
+            let usage = req
+                .usage
+                .as_ref()
+                .map(|u| u.to_string())
+                .unwrap_or_default();
+            result
+                .names
+                .extend(batch.names.into_iter().map(|name| TaggedName {
+                    name,
+                    usage: usage.clone(),
+                    gender: req.gender,
+                }));
+            if batch.stopped.is_some() {
+                result.stopped = batch.stopped;
+                break;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::UsageLimit;
+    use crate::transport::FakeTransport;
+    use http::StatusCode;
+
+    #[test]
+    fn test_largest_remainder() {
+        assert_eq!(largest_remainder(10, [0.6, 0.4].into_iter()), vec![6, 4]);
+        assert_eq!(
+            largest_remainder(10, [1.0, 1.0, 1.0].into_iter()),
+            vec![4, 3, 3]
+        );
+        assert_eq!(largest_remainder(7, [3.0, 2.0].into_iter()), vec![4, 3]);
+        assert_eq!(largest_remainder(0, [3.0, 2.0].into_iter()), vec![0, 0]);
+        assert_eq!(largest_remainder(5, [0.0, 0.0].into_iter()), vec![0, 0]);
+        assert_eq!(
+            largest_remainder(5, [1.0, f64::NAN].into_iter()),
+            vec![0, 0]
+        );
+    }
+
+    #[test]
+    fn test_allocate() {
+        let sampler = WeightedSampler::new()
+            .usage(UsageCode::Italian, 0.6)
+            .usage(UsageCode::English, 0.4)
+            .gender(Gender::Male, 1.0)
+            .gender(Gender::Female, 1.0);
+        let counts: Vec<_> = sampler
+            .allocate(20)
+            .unwrap()
+            .into_iter()
+            .map(|(req, count)| (req.usage.unwrap(), req.gender, count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                (UsageCode::Italian, Gender::Male, 6),
+                (UsageCode::Italian, Gender::Female, 6),
+                (UsageCode::English, Gender::Male, 4),
+                (UsageCode::English, Gender::Female, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(WeightedSampler::new().validate().is_err());
+        assert!(WeightedSampler::new().usage("ita", 1.0).validate().is_ok());
+        for sampler in [
+            WeightedSampler::new().usage("ita", 0.0),
+            WeightedSampler::new().usage("ita", 0.0).usage("eng", 0.0),
+            WeightedSampler::new().usage("ita", f64::NAN),
+            WeightedSampler::new().usage("Eng", 1.0),
+            WeightedSampler::new().usage("ita", 1.0).usage("engl", 1.0),
+            WeightedSampler::new()
+                .usage("ita", 1.0)
+                .gender(Gender::Male, -1.0),
+        ] {
+            match sampler.validate() {
+                Err(Error::InvalidParameter { .. }) => (),
+                r => panic!("{:?} should be rejected: {:?}", sampler, r),
+            }
+            assert!(sampler.allocate(5).is_err());
+        }
+    }
+
+    #[test]
+    fn test_sample_tags_names() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, r#"{"names":["Giulia","Chiara","Sofia"]}"#)
+            .with_response(StatusCode::OK, r#"{"names":["Emily","Grace"]}"#);
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+        let sampler = WeightedSampler::new()
+            .usage("ita", 3.0)
+            .usage("eng", 2.0)
+            .gender(Gender::Female, 1.0);
+
+        let result = sesh.sample(5, &sampler, None);
+        assert!(result.is_complete());
+        assert!(result.stopped.is_none());
+        let tagged: Vec<_> = result
+            .names
+            .iter()
+            .map(|t| (t.name.as_str(), t.usage.as_str(), t.gender))
+            .collect();
+        assert_eq!(
+            tagged,
+            vec![
+                ("Giulia", "ita", Gender::Female),
+                ("Chiara", "ita", Gender::Female),
+                ("Sofia", "ita", Gender::Female),
+                ("Emily", "eng", Gender::Female),
+                ("Grace", "eng", Gender::Female),
+            ]
+        );
+        assert_eq!(
+            sesh.transport().requests(),
+            vec![
+                "https://www.behindthename.com/api/random.json?key=some_key&usage=ita&gender=f&number=3",
+                "https://www.behindthename.com/api/random.json?key=some_key&usage=eng&gender=f&number=2",
+            ]
+        );
+    }
+}
@@ -1,185 +1,540 @@
+use crate::adaptive::{Adaptive, AdaptivePolicy};
+use crate::builder::SessionBuilder;
+use crate::constants::DEFAULT_BASE_URL;
+use crate::error::Error;
+use crate::limiter::{Limit, LimiterMode, QuotaStatus, RateLimiters, Rejected, Window};
+use crate::request::{normalize_base_url, Request, RequestContext};
+use crate::retry::RetryPolicy;
+use crate::transport::{ReqwestTransport, Transport, TransportResponse};
 use crate::types::*;
-use governor::clock::DefaultClock;
-use governor::state::{InMemoryState, NotKeyed};
-use governor::{NotUntil, Quota, RateLimiter};
 use nonzero_ext::nonzero;
-use reqwest::blocking::{Client, Response};
+use reqwest::Client as AsyncClient;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::from_str;
-use std::cmp::max_by;
-use std::cmp::Ordering;
+use std::env;
 use std::fmt::Formatter;
-use std::num::{NonZeroU32, NonZeroU64};
-use std::time::Duration;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 use RateLimited::*;
 
-type DirectRateLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+static STANDARD_LIMITS: [Limit; 4] = [
+    Limit {
+        count: nonzero!(2u64),
+        window: Window::Second,
+    },
+    Limit {
+        count: nonzero!(400u64),
+        window: Window::Hour,
+    },
+    Limit {
+        count: nonzero!(4_000u64),
+        window: Window::Day,
+    },
+    Limit {
+        count: nonzero!(400_000u64),
+        window: Window::Year,
+    },
+];
 
-static DEFAULT_USAGE_LIMIT: UsageLimit = UsageLimit {
-    per_second: nonzero!(2u32),
-    per_hour: nonzero!(400u32),
-    per_day: nonzero!(4_000u64),
-    per_year: nonzero!(400_000u64),
-};
+static API_KEY_VAR: &str = "BTN_API_KEY";
 
-static LIMIT_INTERVALS: [&str; 4] = ["Second", "Hour", "Day", "Year"];
-
-pub struct Session<'a> {
-    pub key: &'a str,
-    limiters: RateLimiters<'a>,
-    client: Client,
+/// A handle on the API for one key. Clones share the same limiters, so a single
+/// quota applies however many handles exist.
+#[derive(Clone)]
+pub struct Session<T = ReqwestTransport> {
+    pub key: String,
+    limiters: Arc<RateLimiters>,
+    transport: T,
+    base_url: String,
+    retry_policy: RetryPolicy,
+    adaptive: Option<Arc<Adaptive>>,
 }
 
-impl std::fmt::Display for Session<'_> {
+impl<T> std::fmt::Display for Session<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Session(key: {}, limiters: {})", self.key, self.limiters)
     }
 }
 
-impl Session<'_> {
-    pub fn new<'a>(key: &'a str, usage_limit: &'a UsageLimit) -> Session<'a> {
+impl Session {
+    pub fn new(key: impl Into<String>, usage_limit: &UsageLimit) -> Session {
+        Session::with_transport(key, usage_limit, ReqwestTransport::default())
+    }
+
+    pub fn new_default(key: impl Into<String>) -> Session {
+        Session::new(key, &UsageLimit::default())
+    }
+
+    /// Creates a default session for the key in the `BTN_API_KEY` environment variable.
+    pub fn from_env() -> Result<Session, env::VarError> {
+        Ok(Session::new_default(env::var(API_KEY_VAR)?))
+    }
+
+    pub fn builder(key: impl Into<String>) -> SessionBuilder {
+        SessionBuilder::new(key)
+    }
+}
+
+impl<T: Transport> Session<T> {
+    pub fn with_transport(
+        key: impl Into<String>,
+        usage_limit: &UsageLimit,
+        transport: T,
+    ) -> Session<T> {
         Session {
-            key,
-            limiters: usage_limit.create_limiters(),
-            client: Client::new(),
+            key: key.into(),
+            limiters: Arc::new(usage_limit.create_limiters()),
+            transport,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            retry_policy: RetryPolicy::none(),
+            adaptive: None,
         }
     }
 
-    pub fn new_default(key: &str) -> Session {
-        Session::new(key, &DEFAULT_USAGE_LIMIT)
+    /// Keeps the limiters' state in the JSON file at `path`, so that quota spent
+    /// by earlier runs still counts. The file is read now, if it exists, and
+    /// rewritten after every call the limiters allow. Clones made before this
+    /// call keep the old, in-memory limiters.
+    pub fn with_state_file(mut self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        self.limiters = Arc::new(RateLimiters::with_state_file(
+            self.limiters.limits().clone(),
+            path,
+        )?);
+        Ok(self)
+    }
+
+    /// Keeps the limiters' state only in the JSON file at `path`, locking it
+    /// around every check. Sessions for the same key, in this process or
+    /// others, that use the same file share one quota.
+    pub fn with_shared_state_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.limiters = Arc::new(RateLimiters::with_shared_state_file(
+            self.limiters.limits().clone(),
+            path,
+        ));
+        self
     }
 
-    fn check(&self) -> Result<(), (&'static str, NotUntil<'_, DefaultInstant>)> {
-        match self.limiters.check() {
-            Ok(_) => Ok(()),
-            Err(earliest) => Err(earliest),
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Tightens the limits while the server reports it is unavailable, and
+    /// relaxes them again after a run of successful calls. Clones made after
+    /// this call share the same effective rate.
+    pub fn with_adaptive(mut self, policy: AdaptivePolicy) -> Self {
+        self.adaptive = Some(Arc::new(Adaptive::new(policy)));
+        self
+    }
+
+    /// The fraction of the configured limits currently in force: 1.0 unless
+    /// adaptive limiting has backed off.
+    pub fn effective_rate(&self) -> f64 {
+        effective_rate(&self.adaptive)
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Result<Self, Error> {
+        self.base_url = normalize_base_url(base_url)?;
+        Ok(self)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// How much of each limit is left, without spending any of it. With
+    /// adaptive limiting, the limits are those currently in force.
+    /// Only fails when a shared state file cannot be read.
+    pub fn quota_status(&self) -> Result<Vec<QuotaStatus>, Error> {
+        self.limiters.status(self.effective_rate())
+    }
+
+    fn context(&self) -> RequestContext<'_> {
+        RequestContext::with_base_url(&self.key, &self.base_url)
+    }
+
+    fn send(&self, url: &str) -> RateLimited<TransportResponse, Error> {
+        match self.transport.get(url) {
+            Err(e) => Failed(e),
+            Ok(resp) => Allowed(resp),
         }
     }
 
-    fn request_internal(&self, req: impl FnOnce(&str) -> String) -> RateLimited<'_, Response, ()> {
-        match self.check() {
-            Err((i, earliest)) => Governed(i, earliest),
-            Ok(_) => match self.client.get(req(self.key)).send() {
-                Err(e) => ReqwestError(e),
-                Ok(resp) => Allowed(resp),
-            },
+    fn request_internal(&self, url: &str) -> RateLimited<TransportResponse, Error> {
+        match self.limiters.check(self.effective_rate()) {
+            Err(Rejected::Limited(i, earliest)) => Governed(i, earliest),
+            Err(Rejected::Store(e)) => Failed(e),
+            Ok(_) => self.send(url),
         }
     }
 
-    pub fn request(
+    fn request_internal_wait(
         &self,
-        req: impl FnOnce(&str) -> String,
-    ) -> RateLimited<'_, JsonResponse, RemoteError> {
-        match self.request_internal(req) {
-            Allowed(resp) => {
-                let text = resp.text().unwrap();
-                let text_str = text.as_str();
-                match from_str::<JsonNameDetails>(text_str) {
-                    Ok(jnd) => Allowed(JsonResponse::NameDetails(jnd)),
-                    Err(_) => match from_str::<JsonNameList>(text_str) {
-                        Ok(jnl) => Allowed(JsonResponse::NameList(jnl)),
-                        Err(_) => match from_str::<RemoteError>(text_str) {
-                            Ok(e) => Failed(e),
-                            Err(_) => panic!("Failed to parse {:?} with any branch", text_str),
-                        },
-                    },
+        url: &str,
+        deadline: Option<Instant>,
+    ) -> RateLimited<TransportResponse, Error> {
+        loop {
+            match self.limiters.check(self.effective_rate()) {
+                Ok(_) => return self.send(url),
+                Err(Rejected::Store(e)) => return Failed(e),
+                Err(Rejected::Limited(i, earliest)) => {
+                    let wait = earliest.wait_time();
+                    if let Some(deadline) = deadline {
+                        if Instant::now() + wait > deadline {
+                            return TimedOut(i, earliest);
+                        }
+                    }
+                    sleep(wait);
                 }
             }
-            Failed(_) => unreachable!(), // we should never generate Limited from the internal request
-            Governed(i, n) => Governed(i, n),
-            ReqwestError(e) => ReqwestError(e),
         }
     }
+
+    /// Runs `attempt` under the retry policy. Gives up with the last failure
+    /// rather than back off past `deadline`, and reports that failure rather
+    /// than a retry the limiters then refused.
+    fn with_retries<S>(
+        &self,
+        deadline: Option<Instant>,
+        mut attempt: impl FnMut() -> RateLimited<S, Error>,
+    ) -> RateLimited<S, Error> {
+        let mut n = 1;
+        let mut last_failure = None;
+        loop {
+            let result = match (attempt(), last_failure) {
+                (Governed(..) | TimedOut(..), Some(e)) => return Failed(e),
+                (result, _) => result,
+            };
+            if !self.retry_policy.should_retry(n, &result) {
+                return result;
+            }
+            let backoff = self.retry_policy.backoff(n);
+            if deadline.is_some_and(|d| Instant::now() + backoff > d) {
+                return result;
+            }
+            last_failure = match result {
+                Failed(e) => Some(e),
+                _ => None,
+            };
+            sleep(backoff);
+            n += 1;
+        }
+    }
+
+    pub fn request<R: Request>(&self, req: R) -> RateLimited<R::Response, Error> {
+        if let Err(e) = req.validate() {
+            return Failed(e);
+        }
+        let url = match req.url(&self.context()) {
+            Ok(url) => url,
+            Err(e) => return Failed(e),
+        };
+        self.with_retries(None, || {
+            observe(&self.adaptive, respond(self.request_internal(&url)))
+        })
+    }
+
+    /// Like `request`, but sleeps until the governing limiter allows the call.
+    /// Gives up with `TimedOut` if the wait would take longer than `max_wait`.
+    /// Retries stop at `max_wait` too, returning the failure they were retrying.
+    pub fn request_wait<R: Request>(
+        &self,
+        req: R,
+        max_wait: Option<Duration>,
+    ) -> RateLimited<R::Response, Error> {
+        if let Err(e) = req.validate() {
+            return Failed(e);
+        }
+        let url = match req.url(&self.context()) {
+            Ok(url) => url,
+            Err(e) => return Failed(e),
+        };
+        let deadline = max_wait.map(|w| Instant::now() + w);
+        self.with_retries(deadline, || {
+            observe(
+                &self.adaptive,
+                respond(self.request_internal_wait(&url, deadline)),
+            )
+        })
+    }
+}
+
+fn respond<R: DeserializeOwned>(
+    resp: RateLimited<TransportResponse, Error>,
+) -> RateLimited<R, Error> {
+    match resp {
+        Allowed(resp) => parse_response(resp),
+        Failed(e) => Failed(e),
+        Governed(i, n) => Governed(i, n),
+        TimedOut(i, n) => TimedOut(i, n),
+    }
+}
+
+fn effective_rate(adaptive: &Option<Arc<Adaptive>>) -> f64 {
+    adaptive.as_ref().map_or(1.0, |a| a.rate())
+}
+
+fn observe<S>(
+    adaptive: &Option<Arc<Adaptive>>,
+    result: RateLimited<S, Error>,
+) -> RateLimited<S, Error> {
+    if let Some(adaptive) = adaptive {
+        adaptive.observe(&result);
+    }
+    result
 }
 
-struct RateLimiters<'a> {
-    limits: &'a UsageLimit,
-    limiters: Vec<DirectRateLimiter>,
+#[derive(Clone)]
+pub struct AsyncSession {
+    pub key: String,
+    limiters: Arc<RateLimiters>,
+    client: AsyncClient,
+    base_url: String,
+    retry_policy: RetryPolicy,
+    adaptive: Option<Arc<Adaptive>>,
 }
 
-impl std::fmt::Display for RateLimiters<'_> {
+impl std::fmt::Display for AsyncSession {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let &UsageLimit {
-            per_second,
-            per_hour,
-            per_day,
-            per_year,
-        } = self.limits;
         write!(
             f,
-            "RateLimiters({} per second, {} per hour, {} per day, {} per year)",
-            per_second, per_hour, per_day, per_year
+            "AsyncSession(key: {}, limiters: {})",
+            self.key, self.limiters
         )
     }
 }
 
-impl RateLimiters<'_> {
-    fn check(&self) -> Result<(), (&'static str, NotUntil<'_, DefaultInstant>)> {
-        let (_, not_untils): (_, Vec<_>) = self
-            .limiters
-            .iter()
-            .enumerate()
-            .map(|(i, item)| (i, item.check()))
-            .partition(|(_, item)| item.is_ok());
-        if not_untils.is_empty() {
-            return Ok(());
-        }
-        let (i, earliest): (usize, Option<NotUntil<'_, DefaultInstant>>) =
-            not_untils.into_iter().fold(
-                (usize::MAX, None),
-                |(i, acc): (usize, Option<NotUntil<'_, DefaultInstant>>),
-                 (j, res): (usize, Result<(), NotUntil<'_, DefaultInstant>>)| {
-                    max_by(
-                        (i, acc),
-                        (j, Some(res.unwrap_err())),
-                        |(_, v1), (_, v2)| match (v1, v2) {
-                            (None, None) => Ordering::Equal,
-                            (Some(_), None) => Ordering::Greater,
-                            (None, Some(_)) => Ordering::Less,
-                            (Some(nu_1), Some(nu_2)) => {
-                                nu_1.earliest_possible().cmp(&nu_2.earliest_possible())
-                            }
-                        },
-                    )
-                },
-            );
-        Err((LIMIT_INTERVALS[i], earliest.unwrap()))
+impl AsyncSession {
+    pub fn new(key: impl Into<String>, usage_limit: &UsageLimit) -> AsyncSession {
+        AsyncSession::with_client(key, usage_limit, AsyncClient::new())
+    }
+
+    pub fn with_client(
+        key: impl Into<String>,
+        usage_limit: &UsageLimit,
+        client: AsyncClient,
+    ) -> AsyncSession {
+        AsyncSession {
+            key: key.into(),
+            limiters: Arc::new(usage_limit.create_limiters()),
+            client,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            retry_policy: RetryPolicy::none(),
+            adaptive: None,
+        }
+    }
+
+    pub fn new_default(key: impl Into<String>) -> AsyncSession {
+        AsyncSession::new(key, &UsageLimit::default())
+    }
+
+    /// Creates a default session for the key in the `BTN_API_KEY` environment variable.
+    pub fn from_env() -> Result<AsyncSession, env::VarError> {
+        Ok(AsyncSession::new_default(env::var(API_KEY_VAR)?))
+    }
+
+    pub fn builder(key: impl Into<String>) -> SessionBuilder {
+        SessionBuilder::new(key)
+    }
+
+    /// See `Session::with_state_file`.
+    pub fn with_state_file(mut self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        self.limiters = Arc::new(RateLimiters::with_state_file(
+            self.limiters.limits().clone(),
+            path,
+        )?);
+        Ok(self)
+    }
+
+    /// See `Session::with_shared_state_file`. `request` never waits on the
+    /// file's lock; while another process holds it, the call polls until it is
+    /// free. The file itself is still read and written synchronously.
+    pub fn with_shared_state_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.limiters = Arc::new(RateLimiters::with_shared_state_file(
+            self.limiters.limits().clone(),
+            path,
+        ));
+        self
+    }
+
+    /// See `Session::with_adaptive`.
+    pub fn with_adaptive(mut self, policy: AdaptivePolicy) -> Self {
+        self.adaptive = Some(Arc::new(Adaptive::new(policy)));
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// See `Session::effective_rate`.
+    pub fn effective_rate(&self) -> f64 {
+        effective_rate(&self.adaptive)
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Result<Self, Error> {
+        self.base_url = normalize_base_url(base_url)?;
+        Ok(self)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// See `Session::quota_status`.
+    pub fn quota_status(&self) -> Result<Vec<QuotaStatus>, Error> {
+        self.limiters.status(self.effective_rate())
+    }
+
+    /// Like `Session::request`, but waits for the limiters to allow the call
+    /// instead of returning `Governed`. Retries follow the session's
+    /// `RetryPolicy`, sleeping on a timer rather than blocking the thread.
+    pub async fn request<R: Request>(&self, req: R) -> RateLimited<R::Response, Error> {
+        if let Err(e) = req.validate() {
+            return Failed(e);
+        }
+        let url = match req.url(&RequestContext::with_base_url(&self.key, &self.base_url)) {
+            Ok(url) => url,
+            Err(e) => return Failed(e),
+        };
+        let mut n = 1;
+        loop {
+            let result = observe(&self.adaptive, self.attempt(&url).await);
+            if !self.retry_policy.should_retry(n, &result) {
+                return result;
+            }
+            futures_timer::Delay::new(self.retry_policy.backoff(n)).await;
+            n += 1;
+        }
+    }
+
+    async fn attempt<S: DeserializeOwned>(&self, url: &str) -> RateLimited<S, Error> {
+        if let Err(e) = self.limiters.until_ready(self.effective_rate()).await {
+            return Failed(e);
+        }
+        match self.client.get(url).send().await {
+            Err(e) => Failed(e.into()),
+            Ok(resp) => {
+                let status = resp.status();
+                match resp.text().await {
+                    Err(e) => Failed(e.into()),
+                    Ok(body) => parse_response(TransportResponse { status, body }),
+                }
+            }
+        }
     }
 }
 
+fn parse_response<R: DeserializeOwned>(resp: TransportResponse) -> RateLimited<R, Error> {
+    let TransportResponse { status, body } = resp;
+    if !status.is_success() {
+        return match from_str::<RemoteError>(&body) {
+            Ok(e) => Failed(Error::Remote(e)),
+            Err(_) => Failed(Error::Status { status, body }),
+        };
+    }
+    match from_str::<R>(&body) {
+        Ok(r) => Allowed(r),
+        Err(source) => match from_str::<RemoteError>(&body) {
+            Ok(e) => Failed(Error::Remote(e)),
+            Err(_) => Failed(Error::Decode { body, source }),
+        },
+    }
+}
+
+/// The limits a session's calls must stay within, all applied at once.
+///
+/// Deserializes from e.g.
+/// `{"limits":[{"count":2,"window":"second"},{"count":30,"window":{"seconds":10}}],"mode":"smoothed"}`;
+/// `mode` may be left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UsageLimit {
-    per_second: NonZeroU32,
-    per_hour: NonZeroU32,
-    per_day: NonZeroU64,
-    per_year: NonZeroU64,
+    pub limits: Vec<Limit>,
+    #[serde(default)]
+    pub mode: LimiterMode,
+}
+
+impl Default for UsageLimit {
+    fn default() -> Self {
+        UsageLimit::standard()
+    }
 }
 
 impl UsageLimit {
-    fn create_limiters(&self) -> RateLimiters {
-        RateLimiters {
-            limits: self,
-            limiters: vec![
-                RateLimiter::direct(
-                    Quota::per_second(self.per_second).allow_burst(self.per_second),
-                ),
-                RateLimiter::direct(Quota::per_hour(self.per_hour).allow_burst(self.per_hour)),
-                RateLimiter::direct(
-                    Quota::with_period(Duration::from_secs(60 * 60 * 24 / self.per_day))
-                        .unwrap()
-                        .allow_burst(NonZeroU32::try_from(self.per_day).unwrap()),
-                ),
-                RateLimiter::direct(
-                    Quota::with_period(Duration::from_secs(60 * 60 * 24 * 365 / self.per_year))
-                        .unwrap()
-                        .allow_burst(NonZeroU32::try_from(self.per_year).unwrap()),
-                ),
-            ],
+    pub fn new(limits: Vec<Limit>) -> UsageLimit {
+        UsageLimit {
+            limits,
+            mode: LimiterMode::default(),
         }
     }
+
+    /// The limits behindthename applies to a standard API key: 2 calls per
+    /// second, 400 per hour, 4,000 per day and 400,000 per year.
+    pub fn standard() -> UsageLimit {
+        UsageLimit::new(STANDARD_LIMITS.to_vec())
+    }
+
+    pub fn with_limit(mut self, limit: Limit) -> Self {
+        self.limits.push(limit);
+        self
+    }
+
+    /// Switches how calls are counted, e.g. to fixed windows that reset at
+    /// midnight UTC the way the server's daily quota does.
+    pub fn with_mode(mut self, mode: LimiterMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn mode(&self) -> LimiterMode {
+        self.mode
+    }
+
+    fn create_limiters(&self) -> RateLimiters {
+        RateLimiters::new(self.clone())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn test_limits(
+        per_second: u64,
+        per_hour: u64,
+        per_day: u64,
+        per_year: u64,
+    ) -> UsageLimit {
+        let limit = |count, window| Limit::new(std::num::NonZeroU64::new(count).unwrap(), window);
+        UsageLimit::new(vec![
+            limit(per_second, Window::Second),
+            limit(per_hour, Window::Hour),
+            limit(per_day, Window::Day),
+            limit(per_year, Window::Year),
+        ])
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lookup::lookup;
+    use crate::random::random;
+    use crate::request::Endpoint;
+    use crate::transport::FakeTransport;
+    use http::StatusCode;
 
     #[test]
     fn test_construct_default_session() {
@@ -188,12 +543,466 @@ mod tests {
 
     #[test]
     fn test_construct_custom_session() {
-        let usage_limit = UsageLimit {
-            per_second: nonzero!(4u32),
-            per_hour: nonzero!(24u32),
-            per_day: nonzero!(90u64),
-            per_year: nonzero!(1000u64),
-        };
+        let usage_limit = UsageLimit::test_limits(4, 24, 90, 1000);
         let _ = Session::new("some_key", &usage_limit);
     }
+
+    #[test]
+    fn test_usage_limit_spec() {
+        let usage_limit: UsageLimit = serde_json::from_str(
+            r#"{"limits":[{"count":2,"window":"second"},{"count":30,"window":{"seconds":10}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            usage_limit,
+            UsageLimit::new(vec![
+                Limit::new(nonzero!(2u64), Window::Second),
+                Limit::new(nonzero!(30u64), Window::Seconds(nonzero!(10u64))),
+            ])
+        );
+        assert_eq!(usage_limit.mode, LimiterMode::Smoothed);
+        assert!(serde_json::from_str::<UsageLimit>(
+            r#"{"limits":[{"count":0,"window":"minute"}]}"#
+        )
+        .is_err());
+        assert_eq!(
+            Session::new("some_key", &usage_limit).to_string(),
+            "Session(key: some_key, limiters: RateLimiters(2 per second, 30 per 10 seconds))"
+        );
+        assert_eq!(
+            Session::new_default("some_key").to_string(),
+            "Session(key: some_key, limiters: RateLimiters(2 per second, 400 per hour, 4000 per day, 400000 per year))"
+        );
+    }
+
+    #[test]
+    fn test_quota_status() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, "[]")
+            .with_response(StatusCode::OK, "[]");
+        let sesh = Session::with_transport(
+            "some_key",
+            &UsageLimit::test_limits(10, 100, 1000, 10000),
+            transport,
+        );
+        let full = sesh.quota_status().unwrap();
+        assert_eq!(
+            full.iter()
+                .map(|s| (s.interval.as_str(), s.limit, s.remaining, s.replenished_in))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Second", 10, 10, Duration::ZERO),
+                ("Hour", 100, 100, Duration::ZERO),
+                ("Day", 1000, 1000, Duration::ZERO),
+                ("Year", 10000, 10000, Duration::ZERO),
+            ]
+        );
+
+        let _ = sesh.request(lookup("Jordan"));
+        let _ = sesh.request(lookup("Jordan"));
+        let status = sesh.quota_status().unwrap();
+        assert_eq!(
+            status.iter().map(|s| s.remaining).collect::<Vec<_>>(),
+            vec![8, 98, 998, 9998]
+        );
+        assert!(status[0].replenished_in <= Duration::from_millis(200));
+        assert!(status[1].replenished_in > Duration::from_secs(71));
+        assert!(status[1].replenished_in <= Duration::from_secs(72));
+
+        let json = serde_json::to_string(&status[2]).unwrap();
+        assert!(json.starts_with(r#"{"interval":"Day","limit":1000,"remaining":998,"#));
+        assert_eq!(
+            serde_json::from_str::<QuotaStatus>(&json).unwrap(),
+            status[2]
+        );
+    }
+
+    #[test]
+    fn test_request_with_custom_window() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, "[]")
+            .with_response(StatusCode::OK, "[]");
+        let usage_limit = UsageLimit::new(vec![
+            Limit::new(nonzero!(5u64), Window::Second),
+            Limit::new(nonzero!(2u64), Window::Seconds(nonzero!(10u64))),
+        ]);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        for _ in 0..2 {
+            match sesh.request(lookup("Jordan")) {
+                Allowed(_) => (),
+                r => panic!("expected the request to be allowed: {:?}", r),
+            }
+        }
+        match sesh.request(lookup("Jordan")) {
+            Governed(i, n) => {
+                assert_eq!(i, "10 seconds");
+                assert!(n.wait_time() > Duration::from_secs(4));
+            }
+            r => panic!("expected the 10-second limit to govern: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_request_with_fake_transport() {
+        let transport = FakeTransport::new()
+            .with_response(
+                StatusCode::OK,
+                r#"[{"name":"Jordan","gender":"mf","usages":[{"usage_code":"eng","usage_full":"English","usage_gender":"mf"}]}]"#,
+            )
+            .with_response(StatusCode::OK, r#"{"names":["Giulia","Rossi"]}"#)
+            .with_response(
+                StatusCode::OK,
+                r#"{"error_code":50,"error":"name could not be found"}"#,
+            );
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        match sesh.request(lookup("Jordan")) {
+            Allowed(JsonNameDetails(items)) => {
+                assert_eq!(items[0].name, "Jordan")
+            }
+            _ => panic!("expected name details"),
+        }
+        match sesh.request(&random()) {
+            Allowed(jnl) => assert_eq!(jnl.names, vec!["Giulia", "Rossi"]),
+            _ => panic!("expected name list"),
+        }
+        match sesh.request(lookup("Qwzx")) {
+            Failed(Error::Remote(e)) => assert_eq!(e.error_code, 50),
+            _ => panic!("expected remote error"),
+        }
+        assert_eq!(
+            sesh.transport().requests()[0],
+            "https://www.behindthename.com/api/lookup.json?key=some_key&name=Jordan"
+        );
+    }
+
+    #[test]
+    fn test_request_with_custom_endpoint() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, r#"{"names":["Giulia"]}"#)
+            .with_response(StatusCode::OK, r#"{"names":["Giulia"]}"#);
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+        let endpoint = |ctx: &RequestContext| ctx.url("api/random.json", vec![("key", ctx.key)]);
+
+        match sesh.request(Endpoint::<_, JsonResponse>::new(endpoint)) {
+            Allowed(JsonResponse::NameList(jnl)) => assert_eq!(jnl.names, vec!["Giulia"]),
+            r => panic!("expected an untyped name list: {:?}", r),
+        }
+        match sesh.request(Endpoint::<_, JsonNameDetails>::new(endpoint)) {
+            Failed(Error::Decode { body, .. }) => assert_eq!(body, r#"{"names":["Giulia"]}"#),
+            r => panic!("a name list should not decode as name details: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_invalid_request_spends_no_quota() {
+        let transport = FakeTransport::new().with_response(StatusCode::OK, "[]");
+        let usage_limit = UsageLimit::test_limits(1, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        match sesh.request(lookup("  ")) {
+            Failed(Error::InvalidParameter { parameter, .. }) => assert_eq!(parameter, "name"),
+            r => panic!("expected a local validation error: {:?}", r),
+        }
+        match sesh.request(lookup("Jordan")) {
+            Allowed(_) => (),
+            r => panic!("the limiter should still have a token: {:?}", r),
+        }
+        assert_eq!(sesh.transport().requests().len(), 1);
+    }
+
+    #[test]
+    fn test_request_with_base_url() {
+        let transport = FakeTransport::new().with_response(StatusCode::OK, "[]");
+        let sesh = Session::with_transport("some_key", &UsageLimit::default(), transport)
+            .with_base_url("http://localhost:8080/mirror")
+            .unwrap();
+        assert_eq!(sesh.base_url(), "http://localhost:8080/mirror/");
+
+        let _ = sesh.request(lookup("Jordan"));
+        assert_eq!(
+            sesh.transport().requests(),
+            vec!["http://localhost:8080/mirror/api/lookup.json?key=some_key&name=Jordan"]
+        );
+        assert!(sesh.clone().with_base_url("mailto:foo").is_err());
+    }
+
+    #[test]
+    fn test_request_undecodable_body() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, "<html>oops</html>")
+            .with_response(StatusCode::OK, r#"[{"name":"Jor"#)
+            .with_response(StatusCode::BAD_GATEWAY, "<html>bad gateway</html>")
+            .with_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                r#"{"error_code":2,"error":"service not available"}"#,
+            );
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        match sesh.request(lookup("Jordan")) {
+            Failed(Error::Decode { body, .. }) => assert_eq!(body, "<html>oops</html>"),
+            _ => panic!("expected a decode error"),
+        }
+        match sesh.request(lookup("Jordan")) {
+            Failed(Error::Decode { body, .. }) => assert_eq!(body, r#"[{"name":"Jor"#),
+            _ => panic!("expected a decode error"),
+        }
+        match sesh.request(lookup("Jordan")) {
+            Failed(Error::Status { status, .. }) => assert_eq!(status, StatusCode::BAD_GATEWAY),
+            _ => panic!("expected a status error"),
+        }
+        match sesh.request(lookup("Jordan")) {
+            Failed(Error::Remote(e)) => assert_eq!(e.error_code, 2),
+            _ => panic!("expected a remote error"),
+        }
+    }
+
+    #[test]
+    fn test_into_result() {
+        let usage_limit = UsageLimit::test_limits(1, 10, 10, 10);
+        let transport = FakeTransport::new().with_response(StatusCode::OK, "[]");
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        fn lookup_twice(sesh: &Session<FakeTransport>) -> Result<JsonNameDetails, Error> {
+            sesh.request(lookup("Jordan")).into_result()?;
+            sesh.request(lookup("Jordan")).into_result()
+        }
+        match lookup_twice(&sesh) {
+            Err(Error::Governed { interval, wait }) => {
+                assert_eq!(interval, "Second");
+                assert!(wait <= Duration::from_secs(1));
+            }
+            r => panic!("expected the second lookup to be governed: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_request_wait() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, "[]")
+            .with_response(StatusCode::OK, "[]");
+        let usage_limit = UsageLimit::test_limits(1, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport);
+
+        match sesh.request(lookup("Jordan")) {
+            Allowed(_) => (),
+            _ => panic!("first request should be allowed"),
+        }
+        match sesh.request_wait(lookup("Jordan"), Some(Duration::from_millis(1))) {
+            TimedOut(i, _) => assert_eq!(i, "Second"),
+            _ => panic!("second request should time out"),
+        }
+        match sesh.request_wait(lookup("Jordan"), None) {
+            Allowed(_) => (),
+            _ => panic!("third request should wait and be allowed"),
+        }
+        assert_eq!(sesh.transport().requests().len(), 2);
+    }
+
+    #[test]
+    fn test_request_retries_service_unavailable() {
+        let unavailable = r#"{"error_code":2,"error":"service not available"}"#;
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, unavailable)
+            .with_response(StatusCode::OK, unavailable)
+            .with_response(StatusCode::OK, "[]")
+            .with_response(StatusCode::OK, unavailable)
+            .with_response(StatusCode::OK, unavailable);
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport).with_retry_policy(
+            RetryPolicy::default()
+                .max_attempts(3)
+                .backoff_base(Duration::from_millis(1)),
+        );
+
+        match sesh.request(lookup("Jordan")) {
+            Allowed(_) => (),
+            _ => panic!("third attempt should succeed"),
+        }
+        assert_eq!(sesh.transport().requests().len(), 3);
+
+        match sesh
+            .with_retry_policy(
+                RetryPolicy::default()
+                    .max_attempts(2)
+                    .backoff_base(Duration::from_millis(1)),
+            )
+            .request(lookup("Jordan"))
+        {
+            Failed(Error::Remote(e)) => assert_eq!(e.error_code, 2),
+            _ => panic!("attempts should be exhausted"),
+        }
+    }
+
+    #[test]
+    fn test_request_retries_consume_quota() {
+        let unavailable = r#"{"error_code":2,"error":"service not available"}"#;
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, unavailable)
+            .with_response(StatusCode::OK, unavailable);
+        let usage_limit = UsageLimit::test_limits(10, 2, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport).with_retry_policy(
+            RetryPolicy::default()
+                .max_attempts(5)
+                .backoff_base(Duration::from_millis(1)),
+        );
+
+        match sesh.request(lookup("Jordan")) {
+            Failed(Error::Remote(e)) => assert_eq!(e.error_code, 2),
+            _ => panic!("retries should run out of quota and report the server's error"),
+        }
+        assert_eq!(sesh.transport().requests().len(), 2);
+    }
+
+    #[test]
+    fn test_request_wait_retries_respect_max_wait() {
+        let unavailable = r#"{"error_code":2,"error":"service not available"}"#;
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, unavailable)
+            .with_response(StatusCode::OK, "[]");
+        let usage_limit = UsageLimit::test_limits(10, 10, 10, 10);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport).with_retry_policy(
+            RetryPolicy::default()
+                .max_attempts(3)
+                .backoff_base(Duration::from_secs(30))
+                .jitter(0.0),
+        );
+
+        let started = Instant::now();
+        match sesh.request_wait(lookup("Jordan"), Some(Duration::from_millis(50))) {
+            Failed(Error::Remote(e)) => assert_eq!(e.error_code, 2),
+            _ => panic!("the backoff should not outlast max_wait"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(sesh.transport().requests().len(), 1);
+    }
+
+    #[test]
+    fn test_adaptive_limits_follow_throttling() {
+        let unavailable = r#"{"error_code":2,"error":"service not available"}"#;
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, unavailable)
+            .with_response(StatusCode::OK, "[]")
+            .with_response(StatusCode::OK, "[]");
+        let usage_limit = UsageLimit::test_limits(10, 8, 100, 100);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport).with_adaptive(
+            AdaptivePolicy::default()
+                .backoff(0.5)
+                .recover_after(2)
+                .recovery(2.0),
+        );
+        let hourly = |sesh: &Session<FakeTransport>| sesh.quota_status().unwrap()[1].clone();
+
+        assert!(sesh.request(lookup("Jordan")).into_result().is_err());
+        assert_eq!(sesh.effective_rate(), 0.5);
+        assert_eq!((hourly(&sesh).limit, hourly(&sesh).remaining), (4, 3));
+
+        assert!(sesh.request(lookup("Jordan")).into_result().is_ok());
+        assert_eq!(sesh.effective_rate(), 0.5);
+        assert!(sesh.clone().request(lookup("Jordan")).into_result().is_ok());
+        assert_eq!(sesh.effective_rate(), 1.0);
+        assert_eq!((hourly(&sesh).limit, hourly(&sesh).remaining), (8, 5));
+    }
+
+    #[test]
+    fn test_adaptive_limits_govern_calls() {
+        let unavailable = r#"{"error_code":2,"error":"service not available"}"#;
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, unavailable)
+            .with_response(StatusCode::OK, unavailable);
+        let usage_limit = UsageLimit::test_limits(10, 8, 100, 100);
+        let sesh = Session::with_transport("some_key", &usage_limit, transport)
+            .with_adaptive(AdaptivePolicy::default().backoff(0.5));
+
+        assert!(sesh.request(lookup("Jordan")).into_result().is_err());
+        assert!(sesh.request(lookup("Jordan")).into_result().is_err());
+        match sesh.request(lookup("Jordan")) {
+            Governed(i, _) => assert_eq!(i, "Hour"),
+            r => panic!(
+                "two calls should fill the quarter-rate hourly limit: {:?}",
+                r
+            ),
+        }
+        assert_eq!(sesh.transport().requests().len(), 2);
+    }
+
+    #[test]
+    fn test_session_is_shareable() {
+        fn assert_shareable<S: Send + Sync + Clone + 'static>() {}
+        assert_shareable::<Session>();
+        assert_shareable::<Session<FakeTransport>>();
+        assert_shareable::<AsyncSession>();
+    }
+
+    #[test]
+    fn test_cloned_sessions_share_limiters() {
+        let usage_limit = UsageLimit::test_limits(1, 10, 10, 10);
+        let transport = FakeTransport::new().with_response(StatusCode::OK, "[]");
+        let sesh = Session::with_transport(String::from("some_key"), &usage_limit, transport);
+        let clone = sesh.clone();
+
+        let handle = std::thread::spawn(move || match clone.request(lookup("Jordan")) {
+            Allowed(_) => (),
+            _ => panic!("first request should be allowed"),
+        });
+        handle.join().unwrap();
+        match sesh.request(lookup("Jordan")) {
+            Governed(i, _) => assert_eq!(i, "Second"),
+            _ => panic!("second request should be governed by the shared limiter"),
+        }
+        assert_eq!(sesh.transport().requests().len(), 1);
+    }
+
+    #[test]
+    fn test_construct_default_async_session() {
+        let _ = AsyncSession::new_default("some_key");
+    }
+
+    /// Serves `bodies` in order, one connection each, on a local port.
+    fn serve(bodies: Vec<&'static str>) -> String {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for body in bodies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[tokio::test]
+    async fn test_async_request_retries() {
+        let unavailable = r#"{"error_code":2,"error":"service not available"}"#;
+        let base_url = serve(vec![unavailable, unavailable, "[]"]);
+        let sesh = AsyncSession::new("some_key", &UsageLimit::test_limits(10, 10, 10, 10))
+            .with_base_url(&base_url)
+            .unwrap()
+            .with_retry_policy(
+                RetryPolicy::default()
+                    .max_attempts(3)
+                    .backoff_base(Duration::from_millis(1)),
+            );
+        match sesh.request(lookup("Jordan")).await {
+            Allowed(JsonNameDetails(items)) => assert!(items.is_empty()),
+            r => panic!("the third attempt should succeed: {:?}", r),
+        }
+        assert_eq!(sesh.quota_status().unwrap()[1].remaining, 7);
+    }
 }
@@ -0,0 +1,103 @@
+use crate::error::Error;
+use http::StatusCode;
+use reqwest::blocking::Client;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportResponse {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl TransportResponse {
+    pub fn new(status: StatusCode, body: impl Into<String>) -> TransportResponse {
+        TransportResponse {
+            status,
+            body: body.into(),
+        }
+    }
+}
+
+/// Performs the HTTP GET behind a `Session`: a URL goes in, a status and body come out.
+pub trait Transport {
+    fn get(&self, url: &str) -> Result<TransportResponse, Error>;
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: Client) -> ReqwestTransport {
+        ReqwestTransport { client }
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+        let resp = self.client.get(url).send()?;
+        let status = resp.status();
+        Ok(TransportResponse::new(status, resp.text()?))
+    }
+}
+
+/// In-memory transport for tests: replays queued responses in order and records
+/// every URL it was asked for. Answers `404 Not Found` once the queue is empty.
+/// Clones share the same queue and record.
+#[derive(Debug, Default, Clone)]
+pub struct FakeTransport {
+    responses: Arc<Mutex<VecDeque<TransportResponse>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeTransport {
+    pub fn new() -> FakeTransport {
+        FakeTransport::default()
+    }
+
+    pub fn with_response(self, status: StatusCode, body: impl Into<String>) -> FakeTransport {
+        self.push_response(status, body);
+        self
+    }
+
+    pub fn push_response(&self, status: StatusCode, body: impl Into<String>) {
+        self.responses
+            .lock()
+            .unwrap()
+            .push_back(TransportResponse::new(status, body));
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for FakeTransport {
+    fn get(&self, url: &str) -> Result<TransportResponse, Error> {
+        self.requests.lock().unwrap().push(url.to_string());
+        Ok(self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| TransportResponse::new(StatusCode::NOT_FOUND, "")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_transport_replays_in_order() {
+        let transport = FakeTransport::new()
+            .with_response(StatusCode::OK, "first")
+            .with_response(StatusCode::OK, "second");
+        assert_eq!(transport.get("a").unwrap().body, "first");
+        assert_eq!(transport.get("b").unwrap().body, "second");
+        assert_eq!(transport.get("c").unwrap().status, StatusCode::NOT_FOUND);
+        assert_eq!(transport.requests(), vec!["a", "b", "c"]);
+    }
+}
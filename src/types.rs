@@ -1,5 +1,6 @@
-use governor::clock::{Clock, DefaultClock};
-use governor::NotUntil;
+use crate::error::Error;
+use crate::limiter::NotUntil;
+use crate::usage::UsageCode;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use serde_json::json;
@@ -17,7 +18,7 @@ pub enum Gender {
     #[serde(rename = "mf")]
     #[serde(rename(deserialize = "fm"))]
     Ambiguous,
-    #[serde(rename(serialize = ""))]
+    #[serde(rename(serialize = ""), alias = "")]
     Any,
 }
 
@@ -36,45 +37,236 @@ impl fmt::Display for Gender {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct RemoteError {
     pub error_code: usize,
     pub error: String,
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+impl RemoteError {
+    pub fn kind(&self) -> RemoteErrorKind {
+        RemoteErrorKind::from_code(self.error_code)
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+
+    pub fn is_key_problem(&self) -> bool {
+        self.kind().is_key_problem()
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "remote error {}: {}", self.error_code, self.error)
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+/// The error codes documented by the behindthename API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RemoteErrorKind {
+    /// 1: the key is missing or not recognized.
+    InvalidKey,
+    /// 2: the service is temporarily unavailable, usually because of throttling.
+    ServiceUnavailable,
+    /// 3: the key has used up its request allowance.
+    KeyLimitExceeded,
+    /// 4: a required parameter is missing or a parameter has an invalid value.
+    BadParameters,
+    /// 50: the name could not be found.
+    NameNotFound,
+    Unknown(usize),
+}
+
+impl RemoteErrorKind {
+    pub fn from_code(code: usize) -> RemoteErrorKind {
+        match code {
+            1 => RemoteErrorKind::InvalidKey,
+            2 => RemoteErrorKind::ServiceUnavailable,
+            3 => RemoteErrorKind::KeyLimitExceeded,
+            4 => RemoteErrorKind::BadParameters,
+            50 => RemoteErrorKind::NameNotFound,
+            c => RemoteErrorKind::Unknown(c),
+        }
+    }
+
+    pub fn code(&self) -> usize {
+        match self {
+            RemoteErrorKind::InvalidKey => 1,
+            RemoteErrorKind::ServiceUnavailable => 2,
+            RemoteErrorKind::KeyLimitExceeded => 3,
+            RemoteErrorKind::BadParameters => 4,
+            RemoteErrorKind::NameNotFound => 50,
+            RemoteErrorKind::Unknown(c) => *c,
+        }
+    }
+
+    /// Whether the same call may succeed if made again later.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, RemoteErrorKind::ServiceUnavailable)
+    }
+
+    /// Whether the error is about the API key rather than the request.
+    pub fn is_key_problem(&self) -> bool {
+        matches!(
+            self,
+            RemoteErrorKind::InvalidKey | RemoteErrorKind::KeyLimitExceeded
+        )
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Usage {
     pub usage_code: String,
     pub usage_full: String,
     pub usage_gender: Gender,
 }
 
-#[derive(Deserialize, Debug)]
+impl Usage {
+    /// The catalog entry for `usage_code`, or `UsageCode::Other` if it has none.
+    pub fn code(&self) -> UsageCode {
+        UsageCode::from_code(self.usage_code.as_str())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct JsonNameDetailItem {
     pub name: String,
-    pub gender: String,
+    pub gender: Gender,
     pub usages: Vec<Usage>,
 }
 
-#[derive(Deserialize, Debug)]
+impl JsonNameDetailItem {
+    /// Whether the name is used for both genders, either overall or across its usages.
+    pub fn is_unisex(&self) -> bool {
+        self.gender == Gender::Ambiguous
+            || (self.usages_for(Gender::Male).next().is_some()
+                && self.usages_for(Gender::Female).next().is_some())
+    }
+
+    /// The usages that apply to `gender`. Unisex usages count for both
+    /// `Male` and `Female`, and `Any` matches every usage.
+    pub fn usages_for(&self, gender: Gender) -> impl Iterator<Item = &Usage> {
+        self.usages.iter().filter(move |u| match gender {
+            Gender::Any => true,
+            Gender::Male | Gender::Female => {
+                u.usage_gender == gender || u.usage_gender == Gender::Ambiguous
+            }
+            Gender::Ambiguous | Gender::Neutral => {
+                u.usage_gender == Gender::Ambiguous || u.usage_gender == Gender::Neutral
+            }
+        })
+    }
+
+    /// The overall gender if it is masculine or feminine; otherwise whichever of
+    /// the two the usages lean towards, or `Ambiguous` if they are balanced.
+    pub fn primary_gender(&self) -> Gender {
+        if let Gender::Male | Gender::Female = self.gender {
+            return self.gender;
+        }
+        let count = |g| self.usages.iter().filter(|u| u.usage_gender == g).count();
+        let (male, female) = (count(Gender::Male), count(Gender::Female));
+        match male.cmp(&female) {
+            std::cmp::Ordering::Greater => Gender::Male,
+            std::cmp::Ordering::Less => Gender::Female,
+            std::cmp::Ordering::Equal => self.gender,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct JsonNameDetails(pub Vec<JsonNameDetailItem>);
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct JsonNameList {
     pub names: Vec<String>,
 }
 
-#[derive(Debug)]
+/// Either response shape, for requests whose endpoint is not known statically.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
 pub enum JsonResponse {
     NameDetails(JsonNameDetails),
     NameList(JsonNameList),
 }
 
-pub(crate) type DefaultInstant = <DefaultClock as Clock>::Instant;
+/// What a call fanned out over several requests gathered, which may be less
+/// than it asked for.
+#[derive(Debug)]
+pub struct Partial<T> {
+    pub names: Vec<T>,
+    pub requested: usize,
+    /// Why gathering ended early, if a call failed or the limiters ran out.
+    pub stopped: Option<Error>,
+}
+
+impl<T> Partial<T> {
+    pub(crate) fn new(requested: usize) -> Partial<T> {
+        Partial {
+            names: vec![],
+            requested,
+            stopped: None,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.names.len() >= self.requested
+    }
+}
 
-pub enum RateLimited<'a, S, E> {
+#[derive(Debug)]
+pub enum RateLimited<S, E> {
     Allowed(S),
-    Governed(&'static str, NotUntil<'a, DefaultInstant>),
+    Governed(String, NotUntil),
+    TimedOut(String, NotUntil),
     Failed(E),
-    ReqwestError(reqwest::Error),
+}
+
+impl<S, E: Into<Error>> RateLimited<S, E> {
+    /// Converts the outcome into a `Result`, turning `Governed` and `TimedOut`
+    /// into errors that carry the remaining wait.
+    pub fn into_result(self) -> Result<S, Error> {
+        match self {
+            RateLimited::Allowed(s) => Ok(s),
+            RateLimited::Governed(interval, earliest) => Err(Error::Governed {
+                interval,
+                wait: earliest.wait_time(),
+            }),
+            RateLimited::TimedOut(interval, earliest) => Err(Error::TimedOut {
+                interval,
+                wait: earliest.wait_time(),
+            }),
+            RateLimited::Failed(e) => Err(e.into()),
+        }
+    }
+}
+
+impl<S, E: Into<Error>> From<RateLimited<S, E>> for Result<S, Error> {
+    fn from(outcome: RateLimited<S, E>) -> Self {
+        outcome.into_result()
+    }
+}
+
+impl<S, E: fmt::Display> fmt::Display for RateLimited<S, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateLimited::Allowed(_) => write!(f, "allowed"),
+            RateLimited::Governed(interval, earliest) => write!(
+                f,
+                "governed by the {} limiter for {:?}",
+                interval,
+                earliest.wait_time()
+            ),
+            RateLimited::TimedOut(interval, earliest) => write!(
+                f,
+                "timed out waiting {:?} for the {} limiter",
+                earliest.wait_time(),
+                interval
+            ),
+            RateLimited::Failed(e) => write!(f, "failed: {}", e),
+        }
+    }
 }
@@ -0,0 +1,219 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// Returned when a string is not a usage code known to the catalog.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnknownUsageCode(pub String);
+
+impl fmt::Display for UnknownUsageCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown usage code {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownUsageCode {}
+
+macro_rules! usage_codes {
+    ($($variant:ident => ($code:literal, $full:literal),)*) => {
+        /// A usage (language or culture) code accepted by the API, as listed by behindthename.
+        ///
+        /// The catalog is generated from `data/usages.tsv`. Parsing a string only
+        /// accepts catalog codes; `Other` carries any code the catalog lacks.
+        /// Values compare and hash by `code()`, so `Other("eng")` equals `English`.
+        #[derive(Clone, Debug, Serialize)]
+        #[serde(into = "String")]
+        pub enum UsageCode {
+            $($variant,)*
+            /// A code outside the catalog, sent and reported as is.
+            Other(String),
+        }
+
+        impl UsageCode {
+            /// The catalog entry for `code`, or `Other` if it has none.
+            pub fn from_code(code: impl Into<String>) -> UsageCode {
+                let code = code.into();
+                code.parse().unwrap_or(UsageCode::Other(code))
+            }
+
+            pub const ALL: &'static [UsageCode] = &[$(UsageCode::$variant,)*];
+
+            /// The code sent to the API, e.g. `"eng"`.
+            pub fn code(&self) -> &str {
+                match self {
+                    $(UsageCode::$variant => $code,)*
+                    UsageCode::Other(code) => code,
+                }
+            }
+
+            /// The full name, as it appears in `Usage::usage_full`, e.g. `"English"`.
+            /// `None` for codes outside the catalog.
+            pub fn full_name(&self) -> Option<&'static str> {
+                match self {
+                    $(UsageCode::$variant => Some($full),)*
+                    UsageCode::Other(_) => None,
+                }
+            }
+        }
+
+        impl FromStr for UsageCode {
+            type Err = UnknownUsageCode;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($code => Ok(UsageCode::$variant),)*
+                    _ => Err(UnknownUsageCode(s.to_string())),
+                }
+            }
+        }
+    };
+}
+
+include!(concat!(env!("OUT_DIR"), "/usage_codes.rs"));
+
+impl fmt::Display for UsageCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl PartialEq for UsageCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.code() == other.code()
+    }
+}
+
+impl Eq for UsageCode {}
+
+impl Hash for UsageCode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.code().hash(state)
+    }
+}
+
+impl PartialOrd for UsageCode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for UsageCode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.code().cmp(other.code())
+    }
+}
+
+impl AsRef<str> for UsageCode {
+    fn as_ref(&self) -> &str {
+        self.code()
+    }
+}
+
+impl From<UsageCode> for String {
+    fn from(usage: UsageCode) -> Self {
+        match usage {
+            UsageCode::Other(code) => code,
+            usage => usage.code().to_string(),
+        }
+    }
+}
+
+/// Unlike parsing, deserializing accepts any code, since stored responses may
+/// hold codes the catalog lacks.
+impl<'de> Deserialize<'de> for UsageCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(UsageCode::from_code(String::deserialize(deserializer)?))
+    }
+}
+
+impl TryFrom<String> for UsageCode {
+    type Error = UnknownUsageCode;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// What the request builders accept as a usage. Strings must name a catalog
+/// entry, so typos surface in `validate`; pass `UsageCode::Other` to send a
+/// code the catalog lacks.
+pub trait IntoUsageCode {
+    fn into_usage_code(self) -> Result<UsageCode, UnknownUsageCode>;
+}
+
+impl IntoUsageCode for UsageCode {
+    fn into_usage_code(self) -> Result<UsageCode, UnknownUsageCode> {
+        Ok(self)
+    }
+}
+
+impl IntoUsageCode for &str {
+    fn into_usage_code(self) -> Result<UsageCode, UnknownUsageCode> {
+        self.parse()
+    }
+}
+
+impl IntoUsageCode for String {
+    fn into_usage_code(self) -> Result<UsageCode, UnknownUsageCode> {
+        self.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_usage_code_round_trip() {
+        for usage in UsageCode::ALL {
+            assert_eq!(usage.code().parse::<UsageCode>().as_ref(), Ok(usage));
+            assert_eq!(usage.to_string(), usage.code());
+        }
+        assert_eq!("eng".parse(), Ok(UsageCode::English));
+        assert_eq!(UsageCode::Italian.full_name(), Some("Italian"));
+        assert_eq!("heb".parse(), Ok(UsageCode::Hebrew));
+        assert_eq!(
+            "engl".parse::<UsageCode>(),
+            Err(UnknownUsageCode("engl".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_other_matches_catalog_entries() {
+        let other = UsageCode::Other("eng".to_string());
+        assert_eq!(other, UsageCode::English);
+        let set: HashSet<_> = [other, UsageCode::English].into_iter().collect();
+        assert_eq!(set.len(), 1);
+        assert!(matches!(UsageCode::from_code("eng"), UsageCode::English));
+        assert!(matches!(UsageCode::from_code("xyz"), UsageCode::Other(_)));
+    }
+
+    #[test]
+    fn test_usage_codes_are_unique() {
+        let codes: HashSet<_> = UsageCode::ALL.iter().map(|u| u.code()).collect();
+        assert_eq!(codes.len(), UsageCode::ALL.len());
+    }
+
+    #[test]
+    fn test_usage_code_serde() {
+        assert_eq!(
+            serde_json::to_string(&UsageCode::Russian).unwrap(),
+            r#""rus""#
+        );
+        assert_eq!(
+            serde_json::from_str::<UsageCode>(r#""rus""#).unwrap(),
+            UsageCode::Russian
+        );
+        assert_eq!(
+            serde_json::from_str::<UsageCode>(r#""russ""#).unwrap(),
+            UsageCode::Other("russ".to_string())
+        );
+        assert_eq!(
+            serde_json::to_string(&UsageCode::Other("russ".to_string())).unwrap(),
+            r#""russ""#
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::env;
 use std::thread::sleep;
 use std::time::Duration;
 
-use behindthename::{lookup, random, session, types::*};
+use behindthename::{lookup, random, session, types::*, Error};
 use Gender::*;
 use RateLimited::*;
 
@@ -69,13 +69,9 @@ fn test_json_lookup() {
     let req = lookup::lookup("Jordan");
 
     match sesh.request(req) {
-        Allowed(JsonResponse::NameDetails(e)) => println!("{:?}", e),
-        Allowed(JsonResponse::NameList(e)) => {
-            panic!("request parsed as name list: {:?}", e)
-        }
+        Allowed(e) => println!("{:?}", e),
         Failed(e) => panic!("first request failed: error {:?}", e),
-        Governed(i, n) => panic!("request failed: limiter {}, {:?}", i, n),
-        ReqwestError(e) => panic!("request failed: {:?}", e),
+        Governed(i, n) | TimedOut(i, n) => panic!("request failed: limiter {}, {:?}", i, n),
     };
 
     sleep(Duration::from_secs(2));
@@ -90,13 +86,26 @@ fn test_json_random() {
     let req = random::random_with_params(Male, Some("ita"), Some(2), true);
 
     match sesh.request(req) {
-        Allowed(JsonResponse::NameList(e)) => println!("{:?}", e),
-        Allowed(JsonResponse::NameDetails(e)) => {
-            panic!("request parsed as name details: {:?}", e)
-        }
+        Allowed(e) => println!("{:?}", e),
+        Failed(e) => panic!("first request failed: error {:?}", e),
+        Governed(i, n) | TimedOut(i, n) => panic!("request failed: limiter {}, {:?}", i, n),
+    };
+
+    sleep(Duration::from_secs(2));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_json_lookup_async() {
+    let key_string = env::var("BTN_API_KEY").unwrap_or_else(|_| "none".to_string());
+    let key = key_string.as_str();
+    let sesh = session::AsyncSession::new_default(key);
+    let req = lookup::lookup("Jordan");
+
+    match sesh.request(req).await {
+        Allowed(e) => println!("{:?}", e),
         Failed(e) => panic!("first request failed: error {:?}", e),
-        Governed(i, n) => panic!("request failed: limiter {}, {:?}", i, n),
-        ReqwestError(e) => panic!("request failed: {:?}", e),
+        Governed(i, n) | TimedOut(i, n) => panic!("request failed: limiter {}, {:?}", i, n),
     };
 
     sleep(Duration::from_secs(2));
@@ -115,41 +124,39 @@ fn test_json_service_unavailable() {
 
     match sesh.request(req_1) {
         Allowed(r) => println!("first request: {:?}", r),
-        Governed(i, n) => panic!("first request failed: limiter {}, {:?}", i, n),
+        Governed(i, n) | TimedOut(i, n) => panic!("first request failed: limiter {}, {:?}", i, n),
         Failed(e) => panic!("first request failed: error {:?}", e),
-        ReqwestError(e) => panic!("first request failed: {:?}", e),
     };
     match sesh.request(req_2) {
         Allowed(r) => println!("second request: {:?}", r),
-        Governed(i, n) => panic!("second request failed: limiter {}, {:?}", i, n),
+        Governed(i, n) | TimedOut(i, n) => panic!("second request failed: limiter {}, {:?}", i, n),
         Failed(e) => panic!("first request failed: error {:?}", e),
-        ReqwestError(e) => panic!("second request failed: {:?}", e),
     };
     // whether third or fourth request is the one that fails is up to chance
     match sesh.request(req_3) {
-        Failed(e) => {
+        Failed(Error::Remote(e)) => {
             assert_eq!(
                 (e.error_code, e.error),
                 (2usize, "service not available".to_string())
             );
             println!("got NotAvailable on third request")
         }
+        Failed(e) => panic!("third request failed: error {:?}", e),
         Allowed(r) => match sesh.request(req_4) {
-            Failed(e) => {
+            Failed(Error::Remote(e)) => {
                 assert_eq!(
                     (e.error_code, e.error),
                     (2usize, "service not available".to_string())
                 );
                 println!("got NotAvailable on fourth request")
             }
+            Failed(e) => panic!("fourth request failed: error {:?}", e),
             Allowed(s) => panic!("third and fourth requests succeeded: {:?}, {:?}", r, s),
-            Governed(i, n) => {
+            Governed(i, n) | TimedOut(i, n) => {
                 println! {"third request: {:?}; fourth request: limiter {}, {:?}", r, i, n}
             }
-            ReqwestError(e) => panic!("fourth request failed: {:?}", e),
         },
-        Governed(i, n) => println! {"third request: limiter {}, {:?}", i, n},
-        ReqwestError(e) => panic!("third request failed: {:?}", e),
+        Governed(i, n) | TimedOut(i, n) => println! {"third request: limiter {}, {:?}", i, n},
     };
 
     sleep(Duration::from_secs(2));
@@ -1,4 +1,5 @@
 use behindthename::types::*;
+use behindthename::usage::UsageCode;
 use Gender::*;
 
 #[test]
@@ -57,3 +58,121 @@ fn test_display_gender() {
     assert_eq!("mf", format!("{}", Ambiguous));
     assert_eq!("", format!("{}", Any));
 }
+
+#[test]
+fn test_rate_limited_into_result() {
+    let allowed: RateLimited<u8, behindthename::Error> = RateLimited::Allowed(3);
+    assert_eq!(allowed.into_result().unwrap(), 3);
+
+    let failed: RateLimited<u8, RemoteError> = RateLimited::Failed(RemoteError {
+        error_code: 2,
+        error: "service not available".to_string(),
+    });
+    assert_eq!(
+        format!("{}", failed),
+        "failed: remote error 2: service not available"
+    );
+    match failed.into_result() {
+        Err(behindthename::Error::Remote(e)) => assert_eq!(e.error_code, 2),
+        r => panic!("expected a remote error: {:?}", r),
+    }
+}
+
+#[test]
+fn test_error_is_std_error() {
+    fn boxed() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let failed: RateLimited<(), RemoteError> = RateLimited::Failed(RemoteError {
+            error_code: 1,
+            error: "invalid key".to_string(),
+        });
+        Ok(failed.into_result()?)
+    }
+    assert_eq!(
+        boxed().unwrap_err().to_string(),
+        "remote error 1: invalid key"
+    );
+}
+
+#[test]
+fn test_remote_error_kind() {
+    let unavailable = RemoteError {
+        error_code: 2,
+        error: "service not available".to_string(),
+    };
+    assert_eq!(unavailable.kind(), RemoteErrorKind::ServiceUnavailable);
+    assert!(unavailable.is_retryable());
+    assert!(!unavailable.is_key_problem());
+
+    let invalid_key = RemoteError {
+        error_code: 1,
+        error: "invalid key".to_string(),
+    };
+    assert_eq!(invalid_key.kind(), RemoteErrorKind::InvalidKey);
+    assert!(!invalid_key.is_retryable());
+    assert!(invalid_key.is_key_problem());
+
+    assert_eq!(RemoteErrorKind::from_code(99), RemoteErrorKind::Unknown(99));
+    for code in [1, 2, 3, 4, 50, 99] {
+        assert_eq!(RemoteErrorKind::from_code(code).code(), code);
+    }
+}
+
+#[test]
+fn test_usage_code_from_usage() {
+    let usage: Usage =
+        serde_json::from_str(r#"{"usage_code":"eng","usage_full":"English","usage_gender":"mf"}"#)
+            .unwrap();
+    let code = usage.code();
+    assert_eq!(code, UsageCode::English);
+    assert_eq!(code.full_name(), Some(usage.usage_full.as_str()));
+
+    let usage: Usage =
+        serde_json::from_str(r#"{"usage_code":"zzz","usage_full":"Unlisted","usage_gender":"f"}"#)
+            .unwrap();
+    assert_eq!(usage.code(), UsageCode::Other("zzz".to_string()));
+    assert_eq!(usage.code().full_name(), None);
+}
+
+#[test]
+fn test_name_detail_item_gender() {
+    let JsonNameDetails(items) = serde_json::from_str(
+        r#"[
+            {"name":"Jordan","gender":"fm","usages":[
+                {"usage_code":"eng","usage_full":"English","usage_gender":"mf"},
+                {"usage_code":"jew","usage_full":"Jewish","usage_gender":"m"},
+                {"usage_code":"bibl","usage_full":"Biblical","usage_gender":"m"}
+            ]},
+            {"name":"Andrea","gender":"mf","usages":[
+                {"usage_code":"ita","usage_full":"Italian","usage_gender":"m"},
+                {"usage_code":"ger","usage_full":"German","usage_gender":"f"}
+            ]},
+            {"name":"Emily","gender":"f","usages":[
+                {"usage_code":"eng","usage_full":"English","usage_gender":"f"}
+            ]}
+        ]"#,
+    )
+    .unwrap();
+    let (jordan, andrea, emily) = (&items[0], &items[1], &items[2]);
+
+    assert_eq!(jordan.gender, Ambiguous);
+    assert!(jordan.is_unisex());
+    assert_eq!(jordan.primary_gender(), Male);
+    assert_eq!(jordan.usages_for(Male).count(), 3);
+    assert_eq!(
+        jordan
+            .usages_for(Female)
+            .map(|u| u.usage_code.as_str())
+            .collect::<Vec<_>>(),
+        vec!["eng"]
+    );
+
+    assert!(andrea.is_unisex());
+    assert_eq!(andrea.primary_gender(), Ambiguous);
+    assert_eq!(andrea.usages_for(Ambiguous).count(), 0);
+
+    assert_eq!(emily.gender, Female);
+    assert!(!emily.is_unisex());
+    assert_eq!(emily.primary_gender(), Female);
+    assert_eq!(emily.usages_for(Male).count(), 0);
+    assert_eq!(emily.usages_for(Any).count(), 1);
+}